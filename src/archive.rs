@@ -0,0 +1,193 @@
+//! Writes zip archives
+//!
+//! Entries are stored uncompressed (SPIR-V barely compresses with deflate anyway) and carry a
+//! fixed timestamp, so the same artifacts always produce the same archive.
+//! See the [zip specification](https://pkware.cachefly.net/webdocs/casestudies/APPNOTE.TXT).
+
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+const LOCAL_FILE_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_DIRECTORY_HEADER: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+const VERSION: u16 = 10; // 1.0, stored entries only
+const DOS_DATE: u16 = (1 << 5) | 1; // 1980-01-01
+const DOS_TIME: u16 = 0;
+
+/// Writes the entries (file name -> contents) to a zip file
+pub fn write_zip(path: &Path, entries: &BTreeMap<String, Vec<u8>>) -> io::Result<()> {
+    if entries.len() > u16::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Too many files for a zip archive",
+        ));
+    }
+
+    let mut zip = Vec::new();
+    let mut central_directory = Vec::new();
+    for (name, contents) in entries {
+        let offset = to_u32(zip.len())?;
+        let size = to_u32(contents.len())?;
+        let crc = crc32(contents);
+        let name_len = name.len() as u16;
+
+        put_u32(&mut zip, LOCAL_FILE_HEADER);
+        put_u16(&mut zip, VERSION);
+        put_u16(&mut zip, 0); // flags
+        put_u16(&mut zip, 0); // stored
+        put_u16(&mut zip, DOS_TIME);
+        put_u16(&mut zip, DOS_DATE);
+        put_u32(&mut zip, crc);
+        put_u32(&mut zip, size); // compressed
+        put_u32(&mut zip, size); // uncompressed
+        put_u16(&mut zip, name_len);
+        put_u16(&mut zip, 0); // extra field
+        zip.extend_from_slice(name.as_bytes());
+        zip.extend_from_slice(contents);
+
+        put_u32(&mut central_directory, CENTRAL_DIRECTORY_HEADER);
+        put_u16(&mut central_directory, VERSION); // made by
+        put_u16(&mut central_directory, VERSION); // needed to extract
+        put_u16(&mut central_directory, 0); // flags
+        put_u16(&mut central_directory, 0); // stored
+        put_u16(&mut central_directory, DOS_TIME);
+        put_u16(&mut central_directory, DOS_DATE);
+        put_u32(&mut central_directory, crc);
+        put_u32(&mut central_directory, size);
+        put_u32(&mut central_directory, size);
+        put_u16(&mut central_directory, name_len);
+        put_u16(&mut central_directory, 0); // extra field
+        put_u16(&mut central_directory, 0); // comment
+        put_u16(&mut central_directory, 0); // disk
+        put_u16(&mut central_directory, 0); // internal attributes
+        put_u32(&mut central_directory, 0); // external attributes
+        put_u32(&mut central_directory, offset);
+        central_directory.extend_from_slice(name.as_bytes());
+    }
+
+    let central_directory_offset = to_u32(zip.len())?;
+    let central_directory_size = to_u32(central_directory.len())?;
+    zip.extend_from_slice(&central_directory);
+
+    put_u32(&mut zip, END_OF_CENTRAL_DIRECTORY);
+    put_u16(&mut zip, 0); // disk
+    put_u16(&mut zip, 0); // disk with the central directory
+    put_u16(&mut zip, entries.len() as u16); // entries on this disk
+    put_u16(&mut zip, entries.len() as u16); // entries in total
+    put_u32(&mut zip, central_directory_size);
+    put_u32(&mut zip, central_directory_offset);
+    put_u16(&mut zip, 0); // comment
+
+    fs::write(path, zip)
+}
+
+fn to_u32(len: usize) -> io::Result<u32> {
+    if len > u32::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Archive too large for a zip file",
+        ));
+    }
+    Ok(len as u32)
+}
+
+fn put_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+/// CRC-32 as used by zip (IEEE polynomial, reflected)
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_at(bytes: &[u8], pos: usize) -> u16 {
+        u16::from_le_bytes([bytes[pos], bytes[pos + 1]])
+    }
+
+    fn u32_at(bytes: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]])
+    }
+
+    /// Reads the entries back through the central directory, checking each CRC
+    fn read_zip(bytes: &[u8]) -> BTreeMap<String, Vec<u8>> {
+        let end = bytes.len() - 22;
+        assert_eq!(u32_at(bytes, end), END_OF_CENTRAL_DIRECTORY);
+        let count = u16_at(bytes, end + 10) as usize;
+        let mut pos = u32_at(bytes, end + 16) as usize;
+
+        let mut entries = BTreeMap::new();
+        for _ in 0..count {
+            assert_eq!(u32_at(bytes, pos), CENTRAL_DIRECTORY_HEADER);
+            let crc = u32_at(bytes, pos + 16);
+            let size = u32_at(bytes, pos + 24) as usize;
+            let name_len = u16_at(bytes, pos + 28) as usize;
+            let offset = u32_at(bytes, pos + 42) as usize;
+            let name = String::from_utf8(bytes[pos + 46..pos + 46 + name_len].to_vec()).unwrap();
+            pos += 46 + name_len;
+
+            assert_eq!(u32_at(bytes, offset), LOCAL_FILE_HEADER);
+            assert_eq!(u32_at(bytes, offset + 14), crc);
+            let data = offset + 30 + u16_at(bytes, offset + 26) as usize;
+            let contents = bytes[data..data + size].to_vec();
+            assert_eq!(crc32(&contents), crc);
+            entries.insert(name, contents);
+        }
+        entries
+    }
+
+    #[test]
+    fn crc32_known_vectors() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"a"), 0xe8b7_be43);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(
+            crc32(b"The quick brown fox jumps over the lazy dog"),
+            0x414f_a339
+        );
+    }
+
+    #[test]
+    fn archive_round_trip() {
+        let mut entries = BTreeMap::new();
+        entries.insert(
+            String::from("color-frag.spv"),
+            vec![0x03, 0x02, 0x23, 0x07, 1, 2],
+        );
+        entries.insert(String::from("color-vert.spvasm"), b"; SPIR-V\n".to_vec());
+        entries.insert(String::from("empty.h"), Vec::new());
+
+        let path = std::env::temp_dir().join(format!("ve_shader_test_{}.zip", std::process::id()));
+        write_zip(&path, &entries).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(read_zip(&bytes), entries);
+    }
+
+    #[test]
+    fn empty_archive() {
+        let path =
+            std::env::temp_dir().join(format!("ve_shader_test_empty_{}.zip", std::process::id()));
+        write_zip(&path, &BTreeMap::new()).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(bytes.len(), 22);
+        assert!(read_zip(&bytes).is_empty());
+    }
+}
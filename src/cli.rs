@@ -0,0 +1,706 @@
+use crate::CliError;
+use std::{path::PathBuf, str::FromStr};
+use structopt::StructOpt;
+
+// Cli arguments
+#[derive(StructOpt, Debug)]
+#[structopt(name = "veshader")]
+pub struct CliArgs {
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+    /// Output debug info
+    #[structopt(long = "verbose", global = true)]
+    pub verbose: bool,
+    /// Log format: text, json (one record per line)
+    #[structopt(long = "log-format", global = true)]
+    pub log_format: Option<LogFormat>,
+    /// Print the accepted target versions
+    #[structopt(long = "list-targets")]
+    pub list_targets: bool,
+    /// Print the accepted stages of the TYPE instruction
+    #[structopt(long = "list-stages")]
+    pub list_stages: bool,
+    /// Print the accepted optimization levels
+    #[structopt(long = "list-optimizations")]
+    pub list_optimizations: bool,
+    /// Print the accepted presets
+    #[structopt(long = "list-presets")]
+    pub list_presets: bool,
+    /// Print an explanation of an error code, e.g. E_UNKNOWN_STAGE
+    #[structopt(long = "explain")]
+    pub explain: Option<String>,
+    /// ???
+    #[structopt(short = "r", long = "rick")]
+    pub rick: bool,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum Command {
+    /// Compile the shaders
+    Compile {
+        #[structopt(flatten)]
+        shared: SharedArgs,
+        #[structopt(flatten)]
+        output: OutputArgs,
+    },
+    /// Keep running and recompile the shaders when they change
+    Watch {
+        #[structopt(flatten)]
+        shared: SharedArgs,
+        #[structopt(flatten)]
+        output: OutputArgs,
+    },
+    /// Check that the shaders compile, without writing any output
+    Check {
+        #[structopt(flatten)]
+        shared: SharedArgs,
+    },
+    /// Print the inputs, outputs and descriptor bindings of the compiled shaders
+    Reflect {
+        #[structopt(flatten)]
+        shared: SharedArgs,
+    },
+}
+
+// Options of all commands, which control how shaders are found and compiled
+#[derive(StructOpt, Debug)]
+pub struct SharedArgs {
+    /// Specify the shader files to compile using glob, or @file to read one glob per line from a file
+    #[structopt(required_unless_one = &["references", "print-config"])]
+    pub glob: Option<String>,
+    /// Compile exactly the shaders named in this JSON file (e.g. materials), instead of a glob
+    #[structopt(long = "references", conflicts_with = "glob", parse(from_os_str))]
+    pub references: Option<PathBuf>,
+    /// Enable debug
+    #[structopt(short = "d", long = "debug")]
+    pub debug: Option<bool>,
+    /// Shader version: vulkan, vulkan1_0, vulkan1_1, vulkan1_2, vulkan-latest
+    #[structopt(short = "s", long = "target-version")]
+    pub shader_version: Option<TargetVersion>,
+    /// Optimization level: zero, size, performance
+    #[structopt(short = "O", long = "optimization", parse(try_from_str=parse_optimization_level))]
+    pub optimization: Option<shaderc::OptimizationLevel>,
+    /// Target environment: vulkan, opengl (e.g. for ARB_gl_spirv), opengl_compat
+    #[structopt(long = "target-env", parse(try_from_str=parse_target_env))]
+    pub target_env: Option<shaderc::TargetEnv>,
+    /// SPIR-V version, independent of the target version: 1.0, 1.1, 1.2, 1.3, 1.4, 1.5
+    #[structopt(long = "spirv-version", parse(try_from_str=parse_spirv_version))]
+    pub spirv_version: Option<shaderc::SpirvVersion>,
+    /// Shipping build: performance optimization without debug info and without the warning budget of a
+    /// profile, unless -O, -d or --max-warnings say otherwise
+    #[structopt(long = "release")]
+    pub release: bool,
+    /// Settings matching an engine's conventions: wgpu, bevy; other flags and --profile take precedence
+    #[structopt(long = "preset", parse(try_from_str=parse_preset))]
+    pub preset: Option<Preset>,
+    /// Bundle of settings for a platform: webgpu, or a [profile.<name>] table of the config file
+    #[structopt(long = "profile")]
+    pub profile: Option<String>,
+    /// Specify the target
+    #[structopt(short = "t", long = "target")]
+    pub target: Option<u32>,
+    /// Source language: glsl, hlsl
+    #[structopt(short = "x", long = "language", parse(try_from_str=parse_source_language))]
+    pub language: Option<shaderc::SourceLanguage>,
+    /// Map an HLSL register to a descriptor set and binding: <register>:<set>:<binding>, e.g. t0:0:16
+    #[structopt(long = "hlsl-shift")]
+    pub hlsl_shifts: Vec<HlslRegisterBinding>,
+    /// Compile every shader once per variant, e.g. debug,release; defined in [variant.<name>] tables of the config file
+    #[structopt(long = "variants", use_delimiter = true)]
+    pub variants: Vec<String>,
+    /// Define a macro: NAME or NAME=VALUE
+    #[structopt(short = "D", long = "define")]
+    pub defines: Vec<String>,
+    /// Read macro definitions from a file, one NAME=VALUE per line; -D takes precedence
+    #[structopt(long = "defines-file", parse(from_os_str))]
+    pub defines_file: Option<PathBuf>,
+    /// Fold a specialization constant into the shader: <constant_id>=<value>, e.g. 0=16
+    #[structopt(long = "spec-constant")]
+    pub spec_constants: Vec<SpecConstant>,
+    /// Automatically assign bindings to uniforms without an explicit binding
+    #[structopt(long = "auto-bind")]
+    pub auto_bind: bool,
+    // Also compile files without the .glsl (or .hlsl) file extension
+    #[structopt(long = "ignore-extension")]
+    pub ignore_extension: bool,
+    /// Don't compile matched files that other matched files include, as they are headers
+    #[structopt(long = "treat-includes-as-dependencies-only")]
+    pub includes_as_dependencies: bool,
+    /// Compile the matched files in the order of their paths, for the same logs on every run
+    #[structopt(long = "sort")]
+    pub sort: bool,
+    /// Directories to search for `#include <...>` files
+    #[structopt(short = "I", long = "include-dir", parse(from_os_str))]
+    pub include_dirs: Vec<PathBuf>,
+    /// Also search `#include <...>` files in the directory of the compiled file
+    #[structopt(long = "base-include-dir")]
+    pub base_include_dir: bool,
+    /// Resolve includes exactly like glslc: "..." next to the including file, then -I; <...> in -I only
+    #[structopt(long = "glslc-includes")]
+    pub glslc_includes: bool,
+    /// How includes are resolved: quote-relative ("..." next to the file, <...> in -I), all-search (both in -I), gcc ("..." next to the file, then -I), root ("..." in --root)
+    #[structopt(long = "include-mode", conflicts_with = "glslc-includes")]
+    pub include_mode: Option<IncludeMode>,
+    /// Compiler diagnostics to print: error (none, only failures), warning, note (everything glslang reports) [default: note]
+    #[structopt(long = "diagnostic-level")]
+    pub diagnostic_level: Option<DiagnosticLevel>,
+    /// Project root for --include-mode root [default: the directory of the config file]
+    #[structopt(long = "root", parse(from_os_str))]
+    pub root: Option<PathBuf>,
+    /// Include each file only once per shader, like `#pragma once`
+    #[structopt(long = "include-once")]
+    pub include_once: bool,
+    /// File whose contents are prepended to every shader, after the version
+    #[structopt(long = "prelude", parse(from_os_str))]
+    pub prelude: Option<PathBuf>,
+    /// Number of stages of a file that are compiled in parallel
+    #[structopt(long = "threads-per-file", default_value = "1")]
+    pub threads_per_file: usize,
+    /// Retry a failed compilation without optimization, to work around optimizer bugs
+    #[structopt(long = "retry-on-failure")]
+    pub retry_on_failure: bool,
+    /// Print a one-line summary of each compiled module: SPIR-V version, entry points and capabilities
+    #[structopt(long = "module-info")]
+    pub module_info: bool,
+    /// Compile each shader N times without writing outputs, and print the min/median/max compile times
+    #[structopt(long = "bench")]
+    pub bench: Option<usize>,
+    /// Run extra checks on the compiled shaders, e.g. the emitted vertices of geometry shaders and the
+    /// interface removed by the optimizer
+    #[structopt(long = "validate")]
+    pub validate: bool,
+    /// Fail shaders that lack a descriptor binding the schema file requires for their stage, or have it with another type
+    #[structopt(long = "verify-reflection-against", parse(from_os_str))]
+    pub reflection_schema: Option<PathBuf>,
+    /// Print the number of SPIR-V instructions of each compiled shader, and the total at the end
+    #[structopt(long = "instr-count")]
+    pub instr_count: bool,
+    /// Write the preprocessed source of shaders that fail to compile to <name>.failed.glsl
+    #[structopt(long = "dump-on-error")]
+    pub dump_on_error: bool,
+    /// Fail a shader whose SPIR-V binary is larger than this many bytes
+    #[structopt(long = "max-size")]
+    pub max_size: Option<usize>,
+    /// Write the compile times of each file and stage as a Chrome trace (chrome://tracing)
+    #[structopt(long = "trace-timing", parse(from_os_str))]
+    pub trace_timing: Option<PathBuf>,
+    /// Write the results of the run as one JSON document at the end, to this file or `-` for stdout
+    #[structopt(long = "report-json")]
+    pub report_json: Option<String>,
+    /// Fail if the total number of warnings exceeds this budget
+    #[structopt(long = "max-warnings")]
+    pub max_warnings: Option<u32>,
+    /// Don't prepend `#version`, neither from VERSION instructions nor inferred; the sources contain their own
+    #[structopt(long = "no-auto-version")]
+    pub no_auto_version: bool,
+    /// Stage of files without TYPE instruction. Stages are resolved in this order: TYPE, --stage,
+    /// the file extension (e.g. foo.comp with --ignore-extension), the directory with
+    /// --stage-from-dir, --default-stage
+    #[structopt(long = "stage")]
+    pub stage: Option<String>,
+    /// Take the stage of files without TYPE instruction from their directory, e.g. vertex/foo.glsl
+    #[structopt(long = "stage-from-dir")]
+    pub stage_from_dir: bool,
+    /// Stage of files whose stage can't be determined otherwise, e.g. with --ignore-extension
+    #[structopt(long = "default-stage")]
+    pub default_stage: Option<String>,
+    /// Require explicit TYPE and VERSION instructions instead of inferring them
+    #[structopt(long = "strict")]
+    pub strict: bool,
+    /// Print the tree of files each shader includes, without compiling, and exit
+    #[structopt(long = "list-includes")]
+    pub list_includes: bool,
+    /// Print the effective settings, after merging the config file, profile, preset and flags, and exit
+    #[structopt(long = "print-config")]
+    pub print_config: bool,
+    /// Config file [default: veshader.toml, if present]
+    #[structopt(short = "c", long = "config", parse(from_os_str))]
+    pub config: Option<PathBuf>,
+}
+
+// Options of the commands that write compiled shaders
+#[derive(StructOpt, Debug)]
+pub struct OutputArgs {
+    /// Output directory, to place the compiled shader in
+    #[structopt(short = "o", long = "output", required_unless = "archive")]
+    pub output: Option<String>,
+    /// Write all output files into this zip archive, instead of the output directory
+    #[structopt(long = "archive", conflicts_with = "output", parse(from_os_str))]
+    pub archive: Option<PathBuf>,
+    /// Output file naming: dash (`foo-frag.spv`), dot (`foo.frag.spv`)
+    #[structopt(long = "naming")]
+    pub naming: Option<OutputNaming>,
+    /// Stage names used in the output files, e.g. vertex=vs,fragment=fs
+    #[structopt(long = "ext-map")]
+    pub ext_map: Option<ExtensionMap>,
+    /// Output files to write, instead of just the binary: spv, asm, c-header, flat, map, deps, reflect
+    /// (or json)
+    #[structopt(long = "emit", use_delimiter = true)]
+    pub emit: Vec<Emit>,
+    /// Also write the SPIR-V assembly (.spvasm)
+    #[structopt(long = "emit-asm")]
+    pub emit_asm: bool,
+    /// Only write the SPIR-V assembly (.spvasm), without the binary
+    #[structopt(long = "asm-only")]
+    pub asm_only: bool,
+    /// Write the binary assembled from the SPIR-V assembly, to test the round trip through assembly
+    #[structopt(long = "assembly-roundtrip", conflicts_with = "asm-only")]
+    pub assembly_roundtrip: bool,
+    /// Add a hash of the contents to the names of the binaries (foo-frag.a1b2c3d4.spv), for caching
+    #[structopt(long = "emit-hash")]
+    pub emit_hash: bool,
+    /// Write the binary as little-endian 32-bit words, instead of in the byte order of the host
+    #[structopt(long = "output-bytes-as-u32")]
+    pub output_bytes_as_u32: bool,
+    /// Start the SPIR-V assembly with a comment: source file, stage, entry point, optimization, tool version
+    #[structopt(long = "emit-metadata-header")]
+    pub emit_metadata_header: bool,
+    /// Also write a C header (.h) with the SPIR-V as a uint32_t array
+    #[structopt(long = "emit-c-header")]
+    pub emit_c_header: bool,
+    /// Also write the source with all includes inlined (.flat.glsl)
+    #[structopt(long = "flatten-includes")]
+    pub flatten_includes: bool,
+    /// Also write a source map (.map.json) from compiled to original line numbers
+    #[structopt(long = "source-map")]
+    pub source_map: bool,
+    /// Also write the input files of each shader as JSON (.deps.json): the source, sidecar and includes
+    #[structopt(long = "emit-deps-json")]
+    pub emit_deps_json: bool,
+    /// Also write how each shader is compiled, as glslc arguments, to compile_commands.json for language servers
+    #[structopt(long = "emit-compile-db")]
+    pub emit_compile_db: bool,
+    /// Also write an unoptimized build with debug info of each shader (.debug.spv)
+    #[structopt(long = "emit-debug-variant")]
+    pub emit_debug_variant: bool,
+    /// Run this command on every written .spv file, which is passed as the last argument
+    #[structopt(long = "post-process")]
+    pub post_process: Option<String>,
+    /// Also link the stages of each file into one module with spirv-link (foo.spv)
+    #[structopt(long = "link")]
+    pub link: bool,
+    /// Write a JSON manifest of the run: the output files and the warnings of each shader
+    #[structopt(long = "manifest", parse(from_os_str))]
+    pub manifest: Option<PathBuf>,
+    /// Skip shaders whose output files are newer than the shader, its sidecar file and includes
+    #[structopt(long = "no-op-on-no-change")]
+    pub no_op_on_no_change: bool,
+    /// Compile all shaders, even if their outputs are up to date
+    #[structopt(long = "force")]
+    pub force: bool,
+    /// Like --force, after deleting the outputs listed in the manifest of the previous run and the manifest
+    #[structopt(long = "clean")]
+    pub clean: bool,
+    /// Fail if no output files were written, e.g. because all files were skipped
+    #[structopt(long = "fail-on-empty-output")]
+    pub fail_on_empty_output: bool,
+    /// Don't write the binary (.spv) of shaders with warnings, and fail at the end of the run
+    #[structopt(long = "no-binary-on-warning")]
+    pub no_binary_on_warning: bool,
+}
+
+// Vulkan target version
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TargetVersion {
+    #[default]
+    Vulkan1_0,
+    Vulkan1_1,
+    Vulkan1_2,
+}
+
+/// Accepted names of the target versions
+pub const TARGET_VERSIONS: &[(&str, TargetVersion)] = &[
+    ("vulkan", TargetVersion::Vulkan1_0),
+    ("vulkan1_0", TargetVersion::Vulkan1_0),
+    ("vulkan1_1", TargetVersion::Vulkan1_1),
+    ("vulkan1_2", TargetVersion::Vulkan1_2),
+    // the newest version this build knows about
+    ("vulkan-latest", TargetVersion::Vulkan1_2),
+];
+
+impl TargetVersion {
+    pub fn into_bitmask(self) -> u32 {
+        match self {
+            TargetVersion::Vulkan1_0 => 1 << 22,
+            TargetVersion::Vulkan1_1 => 1 << 22 | 1 << 12,
+            TargetVersion::Vulkan1_2 => 1 << 22 | 2 << 12,
+        }
+    }
+
+    /// GLSL version used for shaders that don't specify one
+    pub fn default_glsl_version(self) -> &'static str {
+        match self {
+            TargetVersion::Vulkan1_0 => "450",
+            TargetVersion::Vulkan1_1 | TargetVersion::Vulkan1_2 => "460",
+        }
+    }
+}
+
+impl FromStr for TargetVersion {
+    type Err = CliError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TARGET_VERSIONS
+            .iter()
+            .find(|(name, _)| *name == s)
+            .map(|&(_, version)| version)
+            .ok_or_else(|| CliError::InvalidTarget(String::from(s)))
+    }
+}
+
+// Bundle of settings selected with --profile; explicit flags take precedence
+#[derive(Debug, Default, Clone)]
+pub struct Profile {
+    /// `NAME` or `NAME=VALUE`
+    pub defines: Vec<String>,
+    pub target_version: Option<TargetVersion>,
+    pub target_env: Option<shaderc::TargetEnv>,
+    pub spirv_version: Option<shaderc::SpirvVersion>,
+    pub optimization: Option<shaderc::OptimizationLevel>,
+    pub debug: Option<bool>,
+    pub max_warnings: Option<u32>,
+}
+
+impl Profile {
+    /// Profiles that are available without a config file
+    pub fn builtin(name: &str) -> Option<Profile> {
+        match name {
+            // SPIR-V as consumed by WebGPU implementations
+            "webgpu" => Some(Profile {
+                defines: vec![String::from("WEBGPU=1")],
+                target_version: Some(TargetVersion::Vulkan1_1),
+                target_env: None,
+                spirv_version: Some(shaderc::SpirvVersion::V1_3),
+                optimization: None,
+                debug: None,
+                max_warnings: None,
+            }),
+            _ => None,
+        }
+    }
+}
+
+// Settings selected with --preset, to match the conventions of an engine
+#[derive(Debug, Clone, Copy)]
+pub struct Preset {
+    pub target_version: TargetVersion,
+    pub spirv_version: shaderc::SpirvVersion,
+    pub optimization: shaderc::OptimizationLevel,
+    pub naming: OutputNaming,
+}
+
+/// Accepted names of the presets
+///
+/// Both engines read SPIR-V through naga, which works best with plain SPIR-V 1.0 for Vulkan 1.0.
+pub const PRESETS: &[(&str, Preset)] = &[
+    (
+        "wgpu",
+        Preset {
+            target_version: TargetVersion::Vulkan1_0,
+            spirv_version: shaderc::SpirvVersion::V1_0,
+            optimization: shaderc::OptimizationLevel::Performance,
+            naming: OutputNaming::Dot,
+        },
+    ),
+    // Bevy loads shaders at runtime; unoptimized modules keep the names shown in its errors
+    (
+        "bevy",
+        Preset {
+            target_version: TargetVersion::Vulkan1_0,
+            spirv_version: shaderc::SpirvVersion::V1_0,
+            optimization: shaderc::OptimizationLevel::Zero,
+            naming: OutputNaming::Dot,
+        },
+    ),
+];
+
+/// Converts a &str to a Preset
+pub fn parse_preset(name: &str) -> Result<Preset, String> {
+    PRESETS
+        .iter()
+        .find(|(preset, _)| *preset == name)
+        .map(|&(_, preset)| preset)
+        .ok_or_else(|| format!("Unknown preset: {}", name))
+}
+
+// Format of the log output
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = CliError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(CliError::InvalidLogFormat(String::from(s))),
+        }
+    }
+}
+
+// How `#include "..."` and `#include <...>` are resolved
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum IncludeMode {
+    /// `"..."` next to the including file, `<...>` in the include directories
+    #[default]
+    QuoteRelative,
+    /// Both in the include directories
+    AllSearch,
+    /// `"..."` next to the including file, then in the include directories; `<...>` in the include directories
+    Gcc,
+    /// `"..."` relative to the project root, `<...>` in the include directories
+    Root,
+}
+
+impl FromStr for IncludeMode {
+    type Err = CliError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "quote-relative" => Ok(IncludeMode::QuoteRelative),
+            "all-search" => Ok(IncludeMode::AllSearch),
+            "gcc" => Ok(IncludeMode::Gcc),
+            "root" => Ok(IncludeMode::Root),
+            _ => Err(CliError::InvalidIncludeMode(String::from(s))),
+        }
+    }
+}
+
+// Severities of compiler diagnostics that are printed, from the fewest to all of them
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
+pub enum DiagnosticLevel {
+    Error,
+    Warning,
+    #[default]
+    Note,
+}
+
+impl FromStr for DiagnosticLevel {
+    type Err = CliError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(DiagnosticLevel::Error),
+            "warning" => Ok(DiagnosticLevel::Warning),
+            "note" => Ok(DiagnosticLevel::Note),
+            _ => Err(CliError::InvalidDiagnosticLevel(String::from(s))),
+        }
+    }
+}
+
+// Naming scheme of the output files
+#[derive(Debug, Clone, Copy, Default)]
+pub enum OutputNaming {
+    #[default]
+    Dash,
+    Dot,
+}
+
+impl OutputNaming {
+    pub fn file_name(self, stem: &str, stage: &str, extension: &str) -> String {
+        match self {
+            OutputNaming::Dash => format!("{}-{}.{}", stem, stage, extension),
+            OutputNaming::Dot => format!("{}.{}.{}", stem, stage, extension),
+        }
+    }
+
+    /// Joins two parts of a file name with the separator of the scheme
+    pub fn join(self, first: &str, second: &str) -> String {
+        match self {
+            OutputNaming::Dash => format!("{}-{}", first, second),
+            OutputNaming::Dot => format!("{}.{}", first, second),
+        }
+    }
+}
+
+impl FromStr for OutputNaming {
+    type Err = CliError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dash" => Ok(OutputNaming::Dash),
+            "dot" => Ok(OutputNaming::Dot),
+            _ => Err(CliError::InvalidNaming(String::from(s))),
+        }
+    }
+}
+
+// Stage names of the output files, as pairs of (stage, extension)
+#[derive(Debug, Default)]
+pub struct ExtensionMap(pub Vec<(String, String)>);
+
+impl FromStr for ExtensionMap {
+    type Err = CliError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(|pair| match pair.split_once('=') {
+                Some((stage, extension)) if !stage.is_empty() && !extension.is_empty() => {
+                    Ok((String::from(stage), String::from(extension)))
+                }
+                _ => Err(CliError::InvalidExtensionMap(String::from(s))),
+            })
+            .collect::<Result<_, _>>()
+            .map(ExtensionMap)
+    }
+}
+
+// Kind of output file, selected with --emit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Emit {
+    Spv,
+    Asm,
+    CHeader,
+    Flat,
+    SourceMap,
+    Deps,
+    Reflect,
+}
+
+/// Accepted names of the output files of --emit
+pub const EMIT_FORMATS: &[(&str, Emit)] = &[
+    ("spv", Emit::Spv),
+    ("asm", Emit::Asm),
+    ("c-header", Emit::CHeader),
+    ("flat", Emit::Flat),
+    ("map", Emit::SourceMap),
+    ("deps", Emit::Deps),
+    ("reflect", Emit::Reflect),
+    // alias, since the file is JSON
+    ("json", Emit::Reflect),
+];
+
+impl FromStr for Emit {
+    type Err = CliError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        EMIT_FORMATS
+            .iter()
+            .find(|(name, _)| *name == s)
+            .map(|&(_, emit)| emit)
+            .ok_or_else(|| {
+                let names: Vec<_> = EMIT_FORMATS.iter().map(|(name, _)| *name).collect();
+                CliError::InvalidEmit(format!("{}, expected one of: {}", s, names.join(", ")))
+            })
+    }
+}
+
+// Value baked into a `layout(constant_id = ...)` constant at compile time
+#[derive(Debug, Clone)]
+pub struct SpecConstant {
+    pub id: u32,
+    pub value: String,
+}
+
+impl FromStr for SpecConstant {
+    type Err = CliError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((id, value)) if !value.trim().is_empty() => Ok(SpecConstant {
+                id: id
+                    .trim()
+                    .parse()
+                    .map_err(|_| CliError::InvalidSpecConstant(String::from(s)))?,
+                value: String::from(value.trim()),
+            }),
+            _ => Err(CliError::InvalidSpecConstant(String::from(s))),
+        }
+    }
+}
+
+// HLSL register remapped into a Vulkan descriptor set and binding
+#[derive(Debug, Clone)]
+pub struct HlslRegisterBinding {
+    pub register: String,
+    pub set: String,
+    pub binding: String,
+}
+
+impl FromStr for HlslRegisterBinding {
+    type Err = CliError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let split: Vec<_> = s.split(':').collect();
+        match split.as_slice() {
+            [register, set, binding]
+                if !register.is_empty()
+                    && set.parse::<u32>().is_ok()
+                    && binding.parse::<u32>().is_ok() =>
+            {
+                Ok(HlslRegisterBinding {
+                    register: String::from(*register),
+                    set: String::from(*set),
+                    binding: String::from(*binding),
+                })
+            }
+            _ => Err(CliError::InvalidHlslShift(String::from(s))),
+        }
+    }
+}
+
+/// Accepted names of the optimization levels
+pub const OPTIMIZATION_LEVELS: &[(&str, shaderc::OptimizationLevel)] = &[
+    ("zero", shaderc::OptimizationLevel::Zero),
+    ("size", shaderc::OptimizationLevel::Size),
+    ("performance", shaderc::OptimizationLevel::Performance),
+];
+
+/// Converts a &str to shaderc::OptimizationLevel
+pub fn parse_optimization_level(level: &str) -> Result<shaderc::OptimizationLevel, String> {
+    OPTIMIZATION_LEVELS
+        .iter()
+        .find(|(name, _)| *name == level)
+        .map(|&(_, level)| level)
+        .ok_or_else(|| format!("Failed to parse optimization level: {}", level))
+}
+
+/// Accepted target environments
+pub const TARGET_ENVS: &[(&str, shaderc::TargetEnv)] = &[
+    ("vulkan", shaderc::TargetEnv::Vulkan),
+    ("opengl", shaderc::TargetEnv::OpenGL),
+    ("opengl_compat", shaderc::TargetEnv::OpenGLCompat),
+];
+
+/// Converts a &str to shaderc::TargetEnv
+pub fn parse_target_env(env: &str) -> Result<shaderc::TargetEnv, String> {
+    TARGET_ENVS
+        .iter()
+        .find(|(name, _)| *name == env)
+        .map(|&(_, env)| env)
+        .ok_or_else(|| format!("Failed to parse target environment: {}", env))
+}
+
+/// Accepted SPIR-V versions
+pub const SPIRV_VERSIONS: &[(&str, shaderc::SpirvVersion)] = &[
+    ("1.0", shaderc::SpirvVersion::V1_0),
+    ("1.1", shaderc::SpirvVersion::V1_1),
+    ("1.2", shaderc::SpirvVersion::V1_2),
+    ("1.3", shaderc::SpirvVersion::V1_3),
+    ("1.4", shaderc::SpirvVersion::V1_4),
+    ("1.5", shaderc::SpirvVersion::V1_5),
+];
+
+/// Converts a &str to shaderc::SpirvVersion
+pub fn parse_spirv_version(version: &str) -> Result<shaderc::SpirvVersion, String> {
+    SPIRV_VERSIONS
+        .iter()
+        .find(|(name, _)| *name == version)
+        .map(|&(_, version)| version)
+        .ok_or_else(|| format!("Failed to parse SPIR-V version: {}", version))
+}
+
+/// Converts a &str to shaderc::SourceLanguage
+pub fn parse_source_language(language: &str) -> Result<shaderc::SourceLanguage, String> {
+    use shaderc::SourceLanguage::*;
+    match language {
+        "glsl" => Ok(GLSL),
+        "hlsl" => Ok(HLSL),
+        _ => Err(format!("Failed to parse source language: {}", language)),
+    }
+}
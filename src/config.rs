@@ -0,0 +1,298 @@
+//! Parser for the `veshader.toml` config file
+//!
+//! Only the subset of TOML the config needs is supported: `[table]` headers and
+//! `key = value` pairs, where a value is a string, integer, boolean or a single-line array.
+
+use std::collections::BTreeMap;
+
+/// A value on the right-hand side of `key = value`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// Key-value pairs of one `[table]`
+pub type Table = BTreeMap<String, Value>;
+
+/// Parsed config file; keys before the first table header live in the table named ""
+#[derive(Debug, Default)]
+pub struct Config {
+    tables: BTreeMap<String, Table>,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("line {line}: {message}")]
+pub struct ConfigError {
+    line: usize,
+    message: String,
+}
+
+impl Config {
+    pub fn parse(source: &str) -> Result<Config, ConfigError> {
+        let mut tables: BTreeMap<String, Table> = BTreeMap::new();
+        let mut current = String::new();
+
+        for (idx, line) in source.lines().enumerate() {
+            let error = |message: &str| ConfigError {
+                line: idx + 1,
+                message: String::from(message),
+            };
+
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            // table header
+            if let Some(header) = line.strip_prefix('[') {
+                let name = strip_comment(header)
+                    .strip_suffix(']')
+                    .ok_or_else(|| error("Unterminated table header"))?
+                    .trim();
+                if name.is_empty() {
+                    return Err(error("Empty table name"));
+                }
+                current = String::from(name);
+                tables.entry(current.clone()).or_default();
+                continue;
+            }
+
+            // key = value
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error("Expected `key = value`"))?;
+            let key = key.trim().trim_matches('"');
+            if key.is_empty() {
+                return Err(error("Empty key"));
+            }
+            let mut parser = ValueParser {
+                chars: value.trim().chars().collect(),
+                pos: 0,
+            };
+            let value = parser.value().map_err(|message| error(&message))?;
+            parser.skip_whitespace();
+            if !parser.at_end() && parser.peek() != Some('#') {
+                return Err(error("Unexpected characters after value"));
+            }
+
+            let table = tables.entry(current.clone()).or_default();
+            if table.insert(String::from(key), value).is_some() {
+                return Err(error(&format!("Duplicate key: {}", key)));
+            }
+        }
+
+        Ok(Config { tables })
+    }
+
+    pub fn table(&self, name: &str) -> Option<&Table> {
+        self.tables.get(name)
+    }
+
+    /// Every table with its name, in order of the names
+    pub fn tables(&self) -> impl Iterator<Item = (&str, &Table)> {
+        self.tables
+            .iter()
+            .map(|(name, table)| (name.as_str(), table))
+    }
+}
+
+/// Removes a trailing `# comment` from a line without strings
+fn strip_comment(line: &str) -> &str {
+    line.split('#').next().unwrap_or_default().trim()
+}
+
+struct ValueParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl ValueParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.chars.len()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('"') => self.string().map(Value::String),
+            Some('[') => self.array(),
+            Some(_) => self.bare(),
+            None => Err(String::from("Missing value")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.pos += 1; // opening quote
+        let mut s = String::new();
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            match c {
+                '"' => return Ok(s),
+                '\\' => {
+                    let escaped = self.peek().ok_or("Unterminated string")?;
+                    self.pos += 1;
+                    s.push(match escaped {
+                        'n' => '\n',
+                        't' => '\t',
+                        '"' => '"',
+                        '\\' => '\\',
+                        _ => return Err(format!("Unknown escape sequence: \\{}", escaped)),
+                    });
+                }
+                _ => s.push(c),
+            }
+        }
+        Err(String::from("Unterminated string"))
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.pos += 1; // opening bracket
+        let mut values = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if self.peek() == Some(']') {
+                self.pos += 1;
+                return Ok(Value::Array(values));
+            }
+            values.push(self.value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(']') => {}
+                _ => return Err(String::from("Expected `,` or `]` in array")),
+            }
+        }
+    }
+
+    /// Integer or boolean
+    fn bare(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || c == ',' || c == ']' || c == '#' {
+                break;
+            }
+            self.pos += 1;
+        }
+        let token: String = self.chars[start..self.pos].iter().collect();
+        match token.as_str() {
+            "true" => Ok(Value::Boolean(true)),
+            "false" => Ok(Value::Boolean(false)),
+            _ => token
+                .replace('_', "")
+                .parse()
+                .map(Value::Integer)
+                .map_err(|_| format!("Invalid value: {}", token)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Config {
+        Config::parse(source).expect("valid config")
+    }
+
+    fn error_line(source: &str) -> usize {
+        Config::parse(source).expect_err("invalid config").line
+    }
+
+    #[test]
+    fn keys_before_the_first_header_are_in_the_unnamed_table() {
+        let config = parse("a = 1\n[b]\nc = true\n");
+        assert_eq!(config.table("").unwrap()["a"], Value::Integer(1));
+        assert_eq!(config.table("b").unwrap()["c"], Value::Boolean(true));
+        assert!(config.table("c").is_none());
+    }
+
+    #[test]
+    fn dotted_table_names_are_kept_whole() {
+        let config = parse("[profile.mobile] # comment\noptimization = \"size\"\n");
+        let table = config.table("profile.mobile").unwrap();
+        assert_eq!(table["optimization"], Value::String(String::from("size")));
+        assert_eq!(
+            config.tables().map(|(name, _)| name).collect::<Vec<_>>(),
+            vec!["profile.mobile"]
+        );
+    }
+
+    #[test]
+    fn arrays() {
+        let config = parse("defines = [\"A\", \"B=1\" ,]\nempty = []\nnested = [[1], [2, 3]]\n");
+        let table = config.table("").unwrap();
+        assert_eq!(
+            table["defines"],
+            Value::Array(vec![
+                Value::String(String::from("A")),
+                Value::String(String::from("B=1"))
+            ])
+        );
+        assert_eq!(table["empty"], Value::Array(Vec::new()));
+        assert_eq!(
+            table["nested"],
+            Value::Array(vec![
+                Value::Array(vec![Value::Integer(1)]),
+                Value::Array(vec![Value::Integer(2), Value::Integer(3)])
+            ])
+        );
+    }
+
+    #[test]
+    fn strings_keep_hashes_and_escapes() {
+        let config =
+            parse("a = \"x # y\" # comment\nb = \"tab\\t\\\"quoted\\\"\"\nkey = \"a=b\"\n");
+        let table = config.table("").unwrap();
+        assert_eq!(table["a"], Value::String(String::from("x # y")));
+        assert_eq!(table["b"], Value::String(String::from("tab\t\"quoted\"")));
+        assert_eq!(table["key"], Value::String(String::from("a=b")));
+    }
+
+    #[test]
+    fn integers_and_comments() {
+        let config = parse("# heading\n\nsize = 1_000 # bytes\nneg = -3\n");
+        let table = config.table("").unwrap();
+        assert_eq!(table["size"], Value::Integer(1000));
+        assert_eq!(table["neg"], Value::Integer(-3));
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        assert_eq!(error_line("a = 1\n[table"), 2);
+        assert_eq!(error_line("[]"), 1);
+        assert_eq!(error_line("a = 1\nb\n"), 2);
+        assert_eq!(error_line(" = 1"), 1);
+        assert_eq!(error_line("a = \"open"), 1);
+        assert_eq!(error_line("a = \"\\x\""), 1);
+        assert_eq!(error_line("a = [1, 2"), 1);
+        assert_eq!(error_line("a = 1 2"), 1);
+        assert_eq!(error_line("a = yes"), 1);
+        assert_eq!(error_line("a ="), 1);
+        assert_eq!(error_line("a = 1\na = 2"), 2);
+    }
+}
@@ -0,0 +1,218 @@
+//! Parser for the front matter of a shader file
+//!
+//! The front matter is a block of `//` comments between two `//---` lines at the top of a file,
+//! holding a small subset of YAML: `key: value` pairs, where the value of `defines` is a list,
+//! either inline (`[A, B=1]`) or as `- item` lines.
+
+/// Line that opens and closes the front matter
+pub const DELIMITER: &str = "//---";
+
+/// Settings of a file, as an alternative to the `//#` instructions
+#[derive(Debug, Default, Hash)]
+pub struct FrontMatter {
+    pub stage: Option<String>,
+    pub version: Option<String>,
+    pub entry_point: Option<String>,
+    /// `NAME` or `NAME=VALUE`
+    pub defines: Vec<String>,
+}
+
+/// Parses the lines between the delimiters, the first of which is `first_line` of the file
+pub fn parse(lines: &[String], first_line: usize) -> Result<FrontMatter, String> {
+    let mut front_matter = FrontMatter::default();
+    let mut in_defines = false; // `- item` lines belong to `defines:`
+
+    for (idx, line) in lines.iter().enumerate() {
+        let error = |message: String| format!("line {}: {}", first_line + idx, message);
+
+        let line = line
+            .trim()
+            .strip_prefix("//")
+            .ok_or_else(|| error(String::from("Expected a `//` comment")))?;
+        let line = strip_comment(line);
+        if line.is_empty() {
+            continue;
+        }
+
+        // list item
+        if let Some(item) = line.strip_prefix('-') {
+            if !in_defines {
+                return Err(error(String::from("List item outside of `defines`")));
+            }
+            front_matter
+                .defines
+                .push(String::from(unquote(item.trim())));
+            continue;
+        }
+
+        // key: value
+        let (key, value) = line
+            .split_once(':')
+            .ok_or_else(|| error(String::from("Expected `key: value`")))?;
+        let value = unquote(value.trim());
+        in_defines = false;
+        let single = |value: &str| {
+            if value.is_empty() {
+                Err(error(format!("Missing value: {}", key)))
+            } else {
+                Ok(Some(String::from(value)))
+            }
+        };
+        match key.trim() {
+            "stage" => front_matter.stage = single(value)?,
+            "version" => front_matter.version = single(value)?,
+            "entry" => front_matter.entry_point = single(value)?,
+            "defines" => {
+                if value.is_empty() {
+                    in_defines = true;
+                } else {
+                    let items = value
+                        .strip_prefix('[')
+                        .and_then(|value| value.strip_suffix(']'))
+                        .ok_or_else(|| error(String::from("Expected a list: defines")))?;
+                    front_matter.defines.extend(
+                        items
+                            .split(',')
+                            .map(|item| unquote(item.trim()))
+                            .filter(|item| !item.is_empty())
+                            .map(String::from),
+                    );
+                }
+            }
+            key => return Err(error(format!("Unknown key: {}", key))),
+        }
+    }
+    Ok(front_matter)
+}
+
+/// Removes a trailing `# comment`, which starts the line or follows a space outside of quotes
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (idx, c) in line.char_indices() {
+        match quote {
+            Some(open) if c == open => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' && previous.is_whitespace() => return line[..idx].trim(),
+            None => {}
+        }
+        previous = c;
+    }
+    line.trim()
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in &['"', '\''] {
+        if let Some(inner) = value
+            .strip_prefix(*quote)
+            .and_then(|value| value.strip_suffix(*quote))
+        {
+            return inner;
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_lines(lines: &[&str]) -> Result<FrontMatter, String> {
+        let lines: Vec<String> = lines.iter().map(|line| String::from(*line)).collect();
+        parse(&lines, 2)
+    }
+
+    #[test]
+    fn keys() {
+        let front_matter = parse_lines(&[
+            "// stage: fragment",
+            "//version: 450",
+            "//   entry: shade",
+            "//",
+            "   // defines: [A, B=1]",
+        ])
+        .unwrap();
+        assert_eq!(front_matter.stage.as_deref(), Some("fragment"));
+        assert_eq!(front_matter.version.as_deref(), Some("450"));
+        assert_eq!(front_matter.entry_point.as_deref(), Some("shade"));
+        assert_eq!(front_matter.defines, ["A", "B=1"]);
+    }
+
+    #[test]
+    fn quoting() {
+        let front_matter = parse_lines(&[
+            "// stage: \"vertex\"",
+            "// version: '450 core'",
+            "// entry: \"main # not a comment\"",
+            "// defines: [\"A=1\", 'B']",
+        ])
+        .unwrap();
+        assert_eq!(front_matter.stage.as_deref(), Some("vertex"));
+        assert_eq!(front_matter.version.as_deref(), Some("450 core"));
+        assert_eq!(
+            front_matter.entry_point.as_deref(),
+            Some("main # not a comment")
+        );
+        assert_eq!(front_matter.defines, ["A=1", "B"]);
+    }
+
+    #[test]
+    fn comments() {
+        let front_matter = parse_lines(&[
+            "// # the stage",
+            "// stage: compute # comment",
+            "// version: 450#not a comment",
+            "//# starts the line",
+        ])
+        .unwrap();
+        assert_eq!(front_matter.stage.as_deref(), Some("compute"));
+        assert_eq!(front_matter.version.as_deref(), Some("450#not a comment"));
+    }
+
+    #[test]
+    fn lists() {
+        let front_matter = parse_lines(&[
+            "// defines:",
+            "//   - A",
+            "//   - \"B=2\" # comment",
+            "//",
+            "//   -C",
+            "// stage: vertex",
+        ])
+        .unwrap();
+        assert_eq!(front_matter.defines, ["A", "B=2", "C"]);
+
+        let front_matter = parse_lines(&["// defines: []", "// defines: [A, , B,]"]).unwrap();
+        assert_eq!(front_matter.defines, ["A", "B"]);
+
+        assert_eq!(
+            parse_lines(&["// stage: vertex", "// - A"]).unwrap_err(),
+            "line 3: List item outside of `defines`"
+        );
+        assert_eq!(
+            parse_lines(&["// defines: A, B"]).unwrap_err(),
+            "line 2: Expected a list: defines"
+        );
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        assert_eq!(
+            parse_lines(&["// stage: vertex", "// optimize: true"]).unwrap_err(),
+            "line 3: Unknown key: optimize"
+        );
+        assert_eq!(
+            parse_lines(&["// stage: vertex", "", "// version"]).unwrap_err(),
+            "line 3: Expected a `//` comment"
+        );
+        assert_eq!(
+            parse_lines(&["//", "// version"]).unwrap_err(),
+            "line 3: Expected `key: value`"
+        );
+        assert_eq!(
+            parse_lines(&["// entry: \"\""]).unwrap_err(),
+            "line 2: Missing value: entry"
+        );
+    }
+}
@@ -0,0 +1,33 @@
+//! Notices Ctrl-C, so a batch run can stop between shaders and still write what it has
+//!
+//! The first Ctrl-C only sets a flag; a second one terminates the process as usual.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Installs the Ctrl-C handler
+#[cfg(unix)]
+pub fn install() {
+    extern "C" fn handle_interrupt(_: libc::c_int) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        // a second Ctrl-C terminates
+        unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+        }
+    }
+
+    let handler = handle_interrupt as extern "C" fn(libc::c_int);
+    unsafe {
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+    }
+}
+
+/// Ctrl-C keeps terminating the process immediately on other platforms
+#[cfg(not(unix))]
+pub fn install() {}
+
+/// Whether Ctrl-C was pressed since the handler was installed
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
@@ -0,0 +1,394 @@
+//! Helpers for writing and reading JSON
+
+/// Quotes and escapes a string as a JSON string literal
+pub fn string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// A parsed JSON value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Boolean(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// All string values in this value and its children, without object keys
+    pub fn strings(&self) -> Vec<&str> {
+        match self {
+            Value::String(s) => vec![s.as_str()],
+            Value::Array(values) => values.iter().flat_map(Value::strings).collect(),
+            Value::Object(members) => members
+                .iter()
+                .flat_map(|(_, value)| value.strings())
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Parses a JSON document
+pub fn parse(source: &str) -> Result<Value, String> {
+    let mut parser = Parser {
+        chars: source.chars().collect(),
+        pos: 0,
+    };
+    let value = parser.value().and_then(|value| {
+        parser.skip_whitespace();
+        if parser.at_end() {
+            Ok(value)
+        } else {
+            Err(String::from("Unexpected characters after value"))
+        }
+    });
+    value.map_err(|message| format!("line {}: {}", parser.line(), message))
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.chars.len()
+    }
+
+    /// Line of the current position, for errors
+    fn line(&self) -> usize {
+        self.chars[..self.pos.min(self.chars.len())]
+            .iter()
+            .filter(|c| **c == '\n')
+            .count()
+            + 1
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.peek() == Some(expected) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(format!("Expected `{}`", expected))
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('"') => self.string().map(Value::String),
+            Some('[') => self.array(),
+            Some('{') => self.object(),
+            Some(_) => self.bare(),
+            None => Err(String::from("Missing value")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut s = String::new();
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            match c {
+                '"' => return Ok(s),
+                '\\' => {
+                    let escaped = self.peek().ok_or("Unterminated string")?;
+                    self.pos += 1;
+                    s.push(match escaped {
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        'b' => '\u{8}',
+                        'f' => '\u{c}',
+                        '"' | '\\' | '/' => escaped,
+                        'u' => self.unicode_escape()?,
+                        _ => return Err(format!("Unknown escape sequence: \\{}", escaped)),
+                    });
+                }
+                _ => s.push(c),
+            }
+        }
+        Err(String::from("Unterminated string"))
+    }
+
+    /// The four hex digits after `\u`, and a following low surrogate if needed
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.hex_digits()?;
+        let code = if (0xd800..0xdc00).contains(&high) {
+            if self.peek() != Some('\\') || self.chars.get(self.pos + 1) != Some(&'u') {
+                return Err(String::from("Unpaired surrogate in string"));
+            }
+            self.pos += 2;
+            let low = self.hex_digits()?;
+            if !(0xdc00..0xe000).contains(&low) {
+                return Err(String::from("Unpaired surrogate in string"));
+            }
+            0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+        } else {
+            high
+        };
+        std::char::from_u32(code).ok_or_else(|| String::from("Invalid character in string"))
+    }
+
+    fn hex_digits(&mut self) -> Result<u32, String> {
+        let digits: String = self.chars.iter().skip(self.pos).take(4).collect();
+        self.pos += 4;
+        if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("Invalid escape: \\u{}", digits));
+        }
+        u32::from_str_radix(&digits, 16).map_err(|_| format!("Invalid escape: \\u{}", digits))
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.expect('[')?;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.pos += 1;
+            return Ok(Value::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(']') => {
+                    self.pos += 1;
+                    return Ok(Value::Array(values));
+                }
+                _ => return Err(String::from("Expected `,` or `]` in array")),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, String> {
+        self.expect('{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.pos += 1;
+            return Ok(Value::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.expect(':')?;
+            members.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some('}') => {
+                    self.pos += 1;
+                    return Ok(Value::Object(members));
+                }
+                _ => return Err(String::from("Expected `,` or `}` in object")),
+            }
+        }
+    }
+
+    /// Number, boolean or null
+    fn bare(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if !(c.is_ascii_alphanumeric() || c == '-' || c == '+' || c == '.') {
+                break;
+            }
+            self.pos += 1;
+        }
+        let token: String = self.chars[start..self.pos].iter().collect();
+        match token.as_str() {
+            "true" => Ok(Value::Boolean(true)),
+            "false" => Ok(Value::Boolean(false)),
+            "null" => Ok(Value::Null),
+            _ if is_number(&token) => token
+                .parse()
+                .map(Value::Number)
+                .map_err(|_| format!("Invalid value: {}", token)),
+            _ => Err(format!("Invalid value: {}", token)),
+        }
+    }
+}
+
+/// Whether a token has the JSON number syntax, which is stricter than that of `f64::from_str`
+fn is_number(token: &str) -> bool {
+    fn digits(s: &str) -> (&str, &str) {
+        let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        s.split_at(end)
+    }
+
+    let rest = token.strip_prefix('-').unwrap_or(token);
+    let (integer, mut rest) = digits(rest);
+    if integer.is_empty() || (integer.len() > 1 && integer.starts_with('0')) {
+        return false;
+    }
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let (fraction, after) = digits(fraction);
+        if fraction.is_empty() {
+            return false;
+        }
+        rest = after;
+    }
+    if let Some(exponent) = rest.strip_prefix(|c| c == 'e' || c == 'E') {
+        let exponent = exponent
+            .strip_prefix(|c| c == '+' || c == '-')
+            .unwrap_or(exponent);
+        let (exponent, after) = digits(exponent);
+        if exponent.is_empty() {
+            return false;
+        }
+        rest = after;
+    }
+    rest.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> String {
+        parse(source).unwrap_err()
+    }
+
+    #[test]
+    fn string_round_trip() {
+        let s = "quote \" backslash \\ newline \n tab \t bell \u{7} ü";
+        assert_eq!(
+            string(s),
+            "\"quote \\\" backslash \\\\ newline \\n tab \\t bell \\u0007 ü\""
+        );
+        assert_eq!(parse(&string(s)), Ok(Value::String(String::from(s))));
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(
+            parse(r#""\"\\\/\b\f\n\r\t""#),
+            Ok(Value::String(String::from("\"\\/\u{8}\u{c}\n\r\t")))
+        );
+        assert!(error(r#""\x""#).contains("Unknown escape sequence"));
+    }
+
+    #[test]
+    fn unicode_escapes() {
+        assert_eq!(
+            parse(r#""\u0041\u00fc\u20AC""#),
+            Ok(Value::String(String::from("Aü€")))
+        );
+        // surrogate pair
+        assert_eq!(
+            parse(r#""\ud83d\ude00""#),
+            Ok(Value::String(String::from("\u{1f600}")))
+        );
+        assert!(error(r#""\ud83d""#).contains("Unpaired surrogate"));
+        assert!(error(r#""\ud83d\u0041""#).contains("Unpaired surrogate"));
+        assert!(error(r#""\ude00""#).contains("Invalid character"));
+        assert!(error(r#""\u12""#).contains("Invalid escape"));
+        assert!(error(r#""\u+041""#).contains("Invalid escape"));
+        assert!(error(r#""\u12"#).contains("Invalid escape"));
+    }
+
+    #[test]
+    fn nesting() {
+        let value = parse(
+            r#"{
+                "name": "shadow",
+                "stages": [{"kind": "vertex", "bindings": []}, null, true, false],
+                "empty": {}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            value,
+            Value::Object(vec![
+                (String::from("name"), Value::String(String::from("shadow"))),
+                (
+                    String::from("stages"),
+                    Value::Array(vec![
+                        Value::Object(vec![
+                            (String::from("kind"), Value::String(String::from("vertex"))),
+                            (String::from("bindings"), Value::Array(Vec::new())),
+                        ]),
+                        Value::Null,
+                        Value::Boolean(true),
+                        Value::Boolean(false),
+                    ])
+                ),
+                (String::from("empty"), Value::Object(Vec::new())),
+            ])
+        );
+        assert_eq!(value.strings(), ["shadow", "vertex"]);
+    }
+
+    #[test]
+    fn numbers() {
+        for (source, number) in &[
+            ("0", 0.0),
+            ("-0", 0.0),
+            ("42", 42.0),
+            ("-17", -17.0),
+            ("1.5", 1.5),
+            ("1e3", 1000.0),
+            ("2.5E-1", 0.25),
+            ("1e+2", 100.0),
+        ] {
+            assert_eq!(parse(source), Ok(Value::Number(*number)), "{}", source);
+        }
+        for source in &[
+            "01", "+1", "1.", ".5", "1e", "-", "inf", "NaN", "0x10", "1.5.2",
+        ] {
+            assert!(error(source).contains("Invalid value"), "{}", source);
+        }
+    }
+
+    #[test]
+    fn trailing_commas_are_rejected() {
+        assert!(error("[1, 2,]").contains("Invalid value"));
+        assert!(error(r#"{"a": 1,}"#).contains("Expected `\"`"));
+    }
+
+    #[test]
+    fn truncated_input() {
+        assert_eq!(error(""), "line 1: Missing value");
+        assert_eq!(error("[1, 2"), "line 1: Expected `,` or `]` in array");
+        assert_eq!(
+            error("{\n\"a\": 1"),
+            "line 2: Expected `,` or `}` in object"
+        );
+        assert_eq!(error("{\"a\""), "line 1: Expected `:`");
+        assert_eq!(error("{\"a\":"), "line 1: Missing value");
+        assert_eq!(error("\"abc"), "line 1: Unterminated string");
+        assert_eq!(error("\"abc\\"), "line 1: Unterminated string");
+        assert_eq!(error("[1] 2"), "line 1: Unexpected characters after value");
+    }
+}
@@ -1,91 +1,287 @@
-#![feature(once_cell)]
-
 use log::{debug, error, info, warn};
 use std::{
+    cell::{Cell, RefCell},
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap, HashSet},
+    convert::TryFrom,
     fs,
     fs::File,
-    io::{BufRead, BufReader},
-    lazy::SyncLazy,
+    hash::{Hash, Hasher},
+    io::{self, BufRead, BufReader, Write},
     path::{Path, PathBuf},
-    process::exit,
-    str::FromStr,
+    process::{exit, Command as Process},
+    rc::Rc,
+    thread,
+    time::{Duration, Instant, SystemTime},
 };
 use structopt::StructOpt;
 
-// Cli arguments
-#[derive(StructOpt, Debug)]
-#[structopt(name = "veshader")]
-struct CliArgs {
-    /// Specify the shader files to compile using glob
-    glob: String,
-    /// Enable debug
-    #[structopt(short = "d", long = "debug")]
-    debug: Option<bool>,
-    /// Shader version: vulkan, vulkan1_0, vulkan1_1, vulkan1_2
-    #[structopt(short = "s", long = "target-version")]
-    shader_version: Option<TargetVersion>,
-    /// Optimization level: zero, size, performance
-    #[structopt(short = "O", long = "optimization", parse(try_from_str=parse_optimization_level))]
-    optimization: Option<shaderc::OptimizationLevel>,
-    /// Specify the target
-    #[structopt(short = "t", long = "target")]
-    target: Option<u32>,
-    // Also compile files without the .glsl file extension
-    #[structopt(long = "ignore-extension")]
+mod archive;
+mod cli;
+mod config;
+mod front_matter;
+mod interrupt;
+mod json;
+mod spirv;
+
+use cli::{
+    parse_optimization_level, parse_spirv_version, parse_target_env, CliArgs, Command,
+    DiagnosticLevel, Emit, HlslRegisterBinding, IncludeMode, LogFormat, OutputArgs, OutputNaming,
+    Profile, SharedArgs, SpecConstant, TargetVersion, OPTIMIZATION_LEVELS, PRESETS, SPIRV_VERSIONS,
+    TARGET_ENVS, TARGET_VERSIONS,
+};
+use config::Config;
+use front_matter::FrontMatter;
+
+/// Prints a line like `println!`, but to stderr when stdout carries the JSON report
+macro_rules! output {
+    ($settings:expr, $($arg:tt)*) => {
+        if $settings.report_to_stdout {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Settings shared by all shaders of a run
+#[derive(Clone)]
+struct Settings {
+    debug: bool,
+    /// Macro definitions, `NAME` or `NAME=VALUE`
+    defines: Vec<String>,
+    spirv_version: Option<shaderc::SpirvVersion>,
+    /// Forced GLSL version (`--target`)
+    forced_version: Option<u32>,
+    hlsl_shifts: Vec<HlslRegisterBinding>,
+    auto_bind: bool,
+    /// How many stages of a file are compiled at the same time
+    threads_per_file: usize,
+    /// Where compiled shaders are written, if they are written at all
+    output: Option<Output>,
+    include_dirs: Vec<PathBuf>,
+    base_include_dir: bool,
+    /// Path and contents of the --prelude file
+    prelude: Option<(PathBuf, String)>,
+    spec_constants: Vec<SpecConstant>,
+    /// Every shader is compiled once per variant; once without any variant if empty
+    variants: Vec<Variant>,
+    naming: OutputNaming,
+    /// Overrides of the stage names in output file names
+    stage_extensions: Vec<(shaderc::ShaderKind, String)>,
+    target_version: TargetVersion,
+    target_env: shaderc::TargetEnv,
+    language: shaderc::SourceLanguage,
+    emit_spv: bool,
+    emit_asm: bool,
+    assembly_roundtrip: bool,
+    output_bytes_as_u32: bool,
+    emit_hash: bool,
+    no_binary_on_warning: bool,
+    emit_c_header: bool,
+    /// Start the assembly with a comment describing where it came from
+    emit_metadata_header: bool,
+    flatten_includes: bool,
+    source_map: bool,
+    emit_deps_json: bool,
+    /// Write the interface of each shader as JSON, with `--emit reflect`
+    emit_reflection: bool,
+    emit_compile_db: bool,
+    dump_on_error: bool,
+    /// Command that is run on every written SPIR-V file
+    post_process: Option<String>,
+    /// Link the stages of a file into one module with spirv-link
+    link: bool,
     ignore_extension: bool,
-    /// Output directory, to place the compiled shader in
-    #[structopt(short = "o", long = "output")]
-    output: String,
-    /// Output debug info
-    #[structopt(long = "verbose")]
-    verbose: bool,
-    /// ???
-    #[structopt(short = "r", long = "rick")]
-    rick: bool,
-}
-
-// Vulkan target version
-#[derive(Debug)]
-enum TargetVersion {
-    Vulkan1_0,
-    Vulkan1_1,
-    Vulkan1_2,
+    /// Skip matched files that other matched files include
+    includes_as_dependencies: bool,
+    sort: bool,
+    /// Patterns of the ignore file, relative to the working directory
+    ignored: Vec<glob::Pattern>,
+    strict: bool,
+    stage_from_dir: bool,
+    /// Stage of files without TYPE instruction, before any inference
+    stage: Option<shaderc::ShaderKind>,
+    /// Stage of files without TYPE instruction, as a last resort
+    default_stage: Option<shaderc::ShaderKind>,
+    /// Skip shaders whose outputs are newer than their sources
+    skip_unchanged: bool,
+    /// Compile every block of a file in watch mode, even if it didn't change
+    force: bool,
+    /// Prepend `#version` from the VERSION instruction or the target version
+    auto_version: bool,
+    glslc_includes: bool,
+    include_mode: IncludeMode,
+    /// Compiler diagnostics that are printed
+    diagnostic_level: DiagnosticLevel,
+    /// Project root that `#include "..."` is relative to in the root include mode
+    include_root: PathBuf,
+    include_once: bool,
+    optimization: shaderc::OptimizationLevel,
+    stage_optimization: Vec<(shaderc::ShaderKind, shaderc::OptimizationLevel)>,
+    retry_on_failure: bool,
+    module_info: bool,
+    /// Also write an unoptimized build with debug info of each shader
+    emit_debug_variant: bool,
+    instr_count: bool,
+    validate: bool,
+    /// Descriptor bindings the shaders must have, from --verify-reflection-against
+    reflection_schema: Vec<RequiredBinding>,
+    /// Compile each shader this many times and report the timings, instead of writing outputs
+    bench: Option<usize>,
+    /// Start of the run, if the compile times are traced with --trace-timing
+    trace: Option<Instant>,
+    /// Size budget of a SPIR-V binary in bytes
+    max_size: Option<usize>,
+    reflect: bool,
+    /// The JSON report is printed to stdout, so other output goes to stderr
+    report_to_stdout: bool,
+    /// Settings given as flags, which take precedence over the veshader.toml of a directory
+    explicit: Profile,
+    /// Canonical working directory, where the search for the veshader.toml of a directory stops
+    project_root: Option<PathBuf>,
+    /// Canonical path of the config file that was loaded, if any
+    config_file: Option<PathBuf>,
 }
 
-impl TargetVersion {
-    fn into_bitmask(self) -> u32 {
-        match self {
-            TargetVersion::Vulkan1_0 => 1 << 22,
-            TargetVersion::Vulkan1_1 => 1 << 22 | 1 << 12,
-            TargetVersion::Vulkan1_2 => 1 << 22 | 2 << 12,
-        }
-    }
+// Descriptor binding a schema file requires of the shaders of a stage
+#[derive(Debug, Clone)]
+struct RequiredBinding {
+    kind: shaderc::ShaderKind,
+    set: u32,
+    binding: u32,
+    /// Any type if `None`
+    descriptor: Option<spirv::DescriptorKind>,
+}
+
+// Named set of macro definitions, selected with --variants
+#[derive(Debug, Clone)]
+struct Variant {
+    name: String,
+    defines: Vec<String>,
 }
 
-impl FromStr for TargetVersion {
-    type Err = CliError;
+// What to do with the matched shaders
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Compile,
+    Watch,
+    Check,
+    Reflect,
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "vulkan" | "vulkan1_0" => Ok(TargetVersion::Vulkan1_0),
-            "vulkan1_1" => Ok(TargetVersion::Vulkan1_1),
-            "vulkan1_2" => Ok(TargetVersion::Vulkan1_2),
-            _ => Err(CliError::InvalidTarget(String::from(s))),
-        }
-    }
+// Destination of the output files
+#[derive(Debug, Clone)]
+enum Output {
+    Directory(PathBuf),
+    Archive(PathBuf),
 }
 
-impl Default for TargetVersion {
-    fn default() -> Self {
-        TargetVersion::Vulkan1_0
+/// Accumulated over all shaders of a run
+#[derive(Debug, Default)]
+struct Stats {
+    warnings: u32,
+    /// Names of the output files written to the output directory or archive
+    written: Vec<String>,
+    /// Each compiled shader, with its warnings
+    compiled: Vec<CompiledShader>,
+    /// Files that failed: path, error code and message
+    failed: Vec<(PathBuf, &'static str, String)>,
+    /// Names of the binaries with --emit-hash: name without hash -> written name
+    hashed: BTreeMap<String, String>,
+    /// Binaries not written because of warnings, with --no-binary-on-warning
+    withheld: Vec<String>,
+    /// Compile times of the files and stages with --trace-timing
+    spans: Vec<Span>,
+    /// Compile times of each shader with --bench
+    bench: Vec<(String, Vec<Duration>)>,
+    /// Instructions of all shaders counted with --instr-count
+    instructions: spirv::InstructionCount,
+    counted_shaders: usize,
+    /// Output files to put into the archive: file name -> contents
+    archived: BTreeMap<String, Vec<u8>>,
+    /// Hashes of the blocks of each file whose outputs are up to date, so that watch mode only
+    /// recompiles the blocks that changed
+    block_hashes: HashMap<PathBuf, HashSet<u64>>,
+    /// Entries of compile_commands.json with --emit-compile-db
+    compile_commands: Vec<CompileCommand>,
+    /// Ids of the --spec-constant values that replaced a declaration
+    specialized: HashSet<u32>,
+}
+
+impl Stats {
+    /// Adds the stats of a worker thread
+    fn merge(&mut self, other: Stats) {
+        self.warnings += other.warnings;
+        self.written.extend(other.written);
+        self.compiled.extend(other.compiled);
+        self.failed.extend(other.failed);
+        self.withheld.extend(other.withheld);
+        self.hashed.extend(other.hashed);
+        self.spans.extend(other.spans);
+        self.bench.extend(other.bench);
+        self.instructions.add(other.instructions);
+        self.counted_shaders += other.counted_shaders;
+        self.archived.extend(other.archived);
+        self.block_hashes.extend(other.block_hashes);
+        self.compile_commands.extend(other.compile_commands);
+        self.specialized.extend(other.specialized);
     }
 }
 
+/// Time spent on a file or stage, for --trace-timing
+#[derive(Debug)]
+struct Span {
+    name: String,
+    /// `file` or `stage`
+    category: &'static str,
+    /// Since the start of the run
+    start: Duration,
+    duration: Duration,
+    /// 0 for the main thread, otherwise the --threads-per-file worker
+    thread: usize,
+}
+
+/// How a stage of a file is compiled, as the arguments of an equivalent glslc call
+#[derive(Debug)]
+struct CompileCommand {
+    path: PathBuf,
+    arguments: Vec<String>,
+}
+
+/// Record of a compiled shader, for the summary and the manifest
+#[derive(Debug)]
+struct CompiledShader {
+    path: PathBuf,
+    stage: String,
+    variant: Option<String>,
+    warnings: Vec<String>,
+    /// Of the SPIR-V binary
+    size: Option<usize>,
+    /// Files written for it
+    outputs: Vec<String>,
+}
+
 /// Happens during setup
 #[derive(thiserror::Error, Debug)]
-enum CliError {
+pub enum CliError {
     #[error("Invalid target: {0}")]
     InvalidTarget(String),
+    #[error("Invalid log format: {0}")]
+    InvalidLogFormat(String),
+    #[error("Invalid include mode: {0}")]
+    InvalidIncludeMode(String),
+    #[error("Invalid diagnostic level: {0}")]
+    InvalidDiagnosticLevel(String),
+    #[error("Invalid naming: {0}")]
+    InvalidNaming(String),
+    #[error("Invalid output format: {0}")]
+    InvalidEmit(String),
+    #[error("Invalid extension map, expected <stage>=<extension>,...: {0}")]
+    InvalidExtensionMap(String),
+    #[error("Invalid specialization constant, expected <constant_id>=<value>: {0}")]
+    InvalidSpecConstant(String),
+    #[error("Invalid HLSL register shift, expected <register>:<set>:<binding>: {0}")]
+    InvalidHlslShift(String),
     #[error("Error creating compiler. Is shaderc installed?")]
     CompilerCreation,
     #[error("Invalid glob pattern")]
@@ -94,17 +290,225 @@ enum CliError {
     GlobError(#[from] glob::GlobError),
     #[error("Output folder does not exist: {0}")]
     OutputFolderNonExistant(String),
+    #[error("Include directory does not exist: {0}")]
+    IncludeDirNonExistant(String),
+    #[error("Error reading the prelude file: {0}")]
+    PreludeRead(std::io::Error),
+    #[error("Error reading the response file: {0}")]
+    ResponseFileRead(std::io::Error),
+    #[error("Error reading the reference file: {0}")]
+    ReferenceFileRead(std::io::Error),
+    #[error("Invalid reference file: {0}")]
+    InvalidReferenceFile(String),
+    #[error("No output files were written for {0} matched shaders")]
+    EmptyOutput(usize),
+    #[error("Binaries of {0} shaders with warnings were not written")]
+    WithheldBinaries(usize),
+    #[error("Too many warnings: {0} (allowed: {1})")]
+    TooManyWarnings(u32, u32),
+    #[error("Error reading the ignore file: {0}")]
+    IgnoreFileRead(std::io::Error),
+    #[error("Error reading the defines file: {0}")]
+    DefinesFileRead(std::io::Error),
+    #[error("Invalid defines file: {0}")]
+    InvalidDefinesFile(String),
+    #[error("Error reading the config file: {0}")]
+    ConfigRead(std::io::Error),
+    #[error("Error reading the reflection schema: {0}")]
+    SchemaRead(std::io::Error),
+    #[error("Invalid reflection schema: {0}")]
+    InvalidSchema(String),
+    #[error("Invalid config file: {0}")]
+    Config(#[from] config::ConfigError),
+    #[error("Invalid config value: {0}")]
+    InvalidConfigValue(String),
+    #[error("Unknown profile: {0}")]
+    UnknownProfile(String),
+    #[error("Unknown stage: {0}")]
+    UnknownStage(String),
+    #[error("Unknown variant, expected a [variant.<name>] table in the config file: {0}")]
+    UnknownVariant(String),
+    #[error("Error writing the archive: {0}")]
+    ArchiveWrite(std::io::Error),
+    #[error("Error writing the manifest: {0}")]
+    ManifestWrite(std::io::Error),
+    #[error("Error writing the trace: {0}")]
+    TraceWrite(std::io::Error),
+    #[error("Error writing the report: {0}")]
+    ReportWrite(std::io::Error),
+    #[error("Error writing the compile database: {0}")]
+    CompileDbWrite(std::io::Error),
+    #[error("Interrupted")]
+    Interrupted,
 }
 
 /// Happens during shader compilation; prints the error and continues
 #[derive(thiserror::Error, Debug)]
 enum CompilerError {
-    #[error("Error reading the file")]
-    FileRead(#[from] std::io::Error),
+    #[error("Error reading the file: {0}")]
+    FileRead(#[from] io::Error),
     #[error("Error compiling the shader: {0}")]
     Compilation(String),
     #[error("Unknown shader type: {0}")]
     UnknownShaderType(String),
+    #[error("{0}: {1}")]
+    InvalidInstruction(String, String),
+    #[error("Missing //# {0} instruction, which is required in strict mode")]
+    MissingInstruction(&'static str),
+    #[error("Path is not valid UTF-8: {0}")]
+    NonUnicodePath(String),
+    #[error("Invalid sidecar file {0}: {1}")]
+    InvalidSidecar(String, String),
+    #[error("Invalid config file {0}: {1}")]
+    InvalidDirectoryConfig(String, String),
+    #[error("Invalid front matter in {0}: {1}")]
+    InvalidFrontMatter(String, String),
+    #[error("Post-processing the output of {0} failed: {1}")]
+    PostProcess(String, String),
+    #[error("Linking the stages of {0} failed: {1}")]
+    Link(String, String),
+    #[error("Validation of {0} ({1}) failed: {2}")]
+    Validation(String, String, String),
+    #[error("{0} ({1}) is {2} bytes, which exceeds the size budget of {3} bytes")]
+    TooLarge(String, String, usize, usize),
+    #[error("[{0}] {1}")]
+    Labeled(String, Box<CompilerError>),
+}
+
+impl CliError {
+    /// Stable code of the error, for tools that handle errors by category
+    fn code(&self) -> &'static str {
+        use CliError::*;
+        match self {
+            InvalidTarget(_) => "E_INVALID_TARGET",
+            InvalidLogFormat(_) => "E_INVALID_LOG_FORMAT",
+            InvalidIncludeMode(_) => "E_INVALID_INCLUDE_MODE",
+            InvalidDiagnosticLevel(_) => "E_INVALID_DIAGNOSTIC_LEVEL",
+            InvalidNaming(_) => "E_INVALID_NAMING",
+            InvalidEmit(_) => "E_INVALID_EMIT",
+            InvalidExtensionMap(_) => "E_INVALID_EXTENSION_MAP",
+            InvalidSpecConstant(_) => "E_INVALID_SPEC_CONSTANT",
+            InvalidHlslShift(_) => "E_INVALID_HLSL_SHIFT",
+            CompilerCreation => "E_COMPILER_CREATION",
+            PatternError(_) => "E_INVALID_GLOB_PATTERN",
+            GlobError(_) => "E_INVALID_GLOB",
+            OutputFolderNonExistant(_) => "E_OUTPUT_FOLDER_NOT_FOUND",
+            IncludeDirNonExistant(_) => "E_INCLUDE_DIR_NOT_FOUND",
+            PreludeRead(_) => "E_PRELUDE_READ",
+            ResponseFileRead(_) => "E_RESPONSE_FILE_READ",
+            ReferenceFileRead(_) => "E_REFERENCE_FILE_READ",
+            InvalidReferenceFile(_) => "E_INVALID_REFERENCE_FILE",
+            EmptyOutput(_) => "E_EMPTY_OUTPUT",
+            WithheldBinaries(_) => "E_WITHHELD_BINARIES",
+            TooManyWarnings(_, _) => "E_TOO_MANY_WARNINGS",
+            IgnoreFileRead(_) => "E_IGNORE_FILE_READ",
+            DefinesFileRead(_) => "E_DEFINES_FILE_READ",
+            InvalidDefinesFile(_) => "E_INVALID_DEFINES_FILE",
+            ConfigRead(_) => "E_CONFIG_READ",
+            SchemaRead(_) => "E_SCHEMA_READ",
+            InvalidSchema(_) => "E_INVALID_SCHEMA",
+            Config(_) => "E_INVALID_CONFIG",
+            InvalidConfigValue(_) => "E_INVALID_CONFIG_VALUE",
+            UnknownProfile(_) => "E_UNKNOWN_PROFILE",
+            UnknownStage(_) => "E_UNKNOWN_STAGE",
+            UnknownVariant(_) => "E_UNKNOWN_VARIANT",
+            ArchiveWrite(_) => "E_ARCHIVE_WRITE",
+            ManifestWrite(_) => "E_MANIFEST_WRITE",
+            TraceWrite(_) => "E_TRACE_WRITE",
+            ReportWrite(_) => "E_REPORT_WRITE",
+            CompileDbWrite(_) => "E_COMPILE_DB_WRITE",
+            Interrupted => "E_INTERRUPTED",
+        }
+    }
+}
+
+impl CompilerError {
+    /// Stable code of the error, for tools that handle errors by category
+    fn code(&self) -> &'static str {
+        use CompilerError::*;
+        match self {
+            FileRead(_) => "E_FILE_READ",
+            Compilation(_) => "E_COMPILATION",
+            UnknownShaderType(_) => "E_UNKNOWN_STAGE",
+            InvalidInstruction(_, _) => "E_INVALID_INSTRUCTION",
+            MissingInstruction(_) => "E_MISSING_INSTRUCTION",
+            NonUnicodePath(_) => "E_NON_UNICODE_PATH",
+            InvalidSidecar(_, _) => "E_INVALID_SIDECAR",
+            InvalidDirectoryConfig(_, _) => "E_INVALID_DIRECTORY_CONFIG",
+            InvalidFrontMatter(_, _) => "E_INVALID_FRONT_MATTER",
+            PostProcess(_, _) => "E_POST_PROCESS",
+            Link(_, _) => "E_LINK",
+            Validation(_, _, _) => "E_VALIDATION",
+            TooLarge(_, _, _, _) => "E_TOO_LARGE",
+            Labeled(_, err) => err.code(),
+        }
+    }
+}
+
+/// Explanations of the error codes, printed by --explain
+const EXPLANATIONS: &[(&str, &str)] = &[
+    ("E_INVALID_TARGET", "The target isn't one of the accepted targets. Run `ve_shader --list-targets` for the accepted target versions."),
+    ("E_INVALID_LOG_FORMAT", "--log-format accepts `text` and `json`."),
+    ("E_INVALID_INCLUDE_MODE", "--include-mode accepts `quote-relative`, `all-search`, `gcc` and `root`."),
+    ("E_INVALID_DIAGNOSTIC_LEVEL", "--diagnostic-level accepts `error`, `warning` and `note`."),
+    ("E_INVALID_NAMING", "--naming accepts `dash` (foo-frag.spv) and `dot` (foo.frag.spv)."),
+    ("E_INVALID_EMIT", "--emit expects a comma-separated list of output files: spv, asm, c-header, flat, map, deps and reflect (also called json), e.g. `--emit spv,asm`."),
+    ("E_INVALID_EXTENSION_MAP", "--ext-map expects comma-separated `<stage>=<extension>` pairs, e.g. `vertex=vs,fragment=fs`. The stages are those of `ve_shader --list-stages`."),
+    ("E_INVALID_SPEC_CONSTANT", "--spec-constant expects `<constant_id>=<value>`, e.g. `0=16`, where the id is the `constant_id` of the layout qualifier."),
+    ("E_INVALID_HLSL_SHIFT", "--hlsl-shift expects `<register>:<set>:<binding>`, e.g. `t0:0:16`."),
+    ("E_COMPILER_CREATION", "shaderc couldn't be initialized. Check that the shaderc library is installed and can be found, or build ve_shader with the bundled shaderc (cmake, ninja and Python are required)."),
+    ("E_INVALID_GLOB_PATTERN", "The glob couldn't be parsed. Quote it so that the shell doesn't expand it, and check for unbalanced `[` or `]`."),
+    ("E_INVALID_GLOB", "A path matched by the glob couldn't be read, usually because of missing permissions."),
+    ("E_OUTPUT_FOLDER_NOT_FOUND", "The folder passed with -o doesn't exist. Create it first; ve_shader doesn't create output folders."),
+    ("E_INCLUDE_DIR_NOT_FOUND", "A directory passed with -I doesn't exist. Check the path, which is relative to the working directory."),
+    ("E_PRELUDE_READ", "The file passed with --prelude couldn't be read."),
+    ("E_RESPONSE_FILE_READ", "The response file given with @<file> couldn't be read."),
+    ("E_REFERENCE_FILE_READ", "The file passed with --references couldn't be read."),
+    ("E_INVALID_REFERENCE_FILE", "The file passed with --references isn't valid JSON."),
+    ("E_EMPTY_OUTPUT", "--fail-on-empty-output is set, but no file was written. Check that the glob matches the shaders, that they have TYPE instructions and aren't ignored by .veshaderignore."),
+    ("E_WITHHELD_BINARIES", "--no-binary-on-warning is set, and some shaders had warnings, so their .spv files weren't written. Fix the warnings listed in the log."),
+    ("E_TOO_MANY_WARNINGS", "The run produced more warnings than --max-warnings allows. The summary at the end of the run lists the shaders with warnings."),
+    ("E_IGNORE_FILE_READ", "The .veshaderignore file in the working directory couldn't be read."),
+    ("E_DEFINES_FILE_READ", "The file passed with --defines-file couldn't be read."),
+    ("E_INVALID_DEFINES_FILE", "The defines file must have one `NAME` or `NAME=VALUE` per line; lines starting with `#` are comments."),
+    ("E_SCHEMA_READ", "The schema file passed with --verify-reflection-against couldn't be read."),
+    ("E_INVALID_SCHEMA", "The reflection schema has a table per stage, e.g. `[fragment]`, with `bindings = [\"<set>:<binding>:<type>\", ...]`; the type is optional and one of the types printed by the reflect command, like `uniform_buffer`."),
+    ("E_CONFIG_READ", "The config file couldn't be read. Without -c, veshader.toml in the working directory is used."),
+    ("E_INVALID_CONFIG", "The config file isn't valid TOML."),
+    ("E_INVALID_CONFIG_VALUE", "A value in the config file has the wrong type or an unknown name, e.g. a stage or optimization level. The message names the key."),
+    ("E_UNKNOWN_PROFILE", "--profile names neither the built-in webgpu profile nor a [profile.<name>] table of the config file."),
+    ("E_UNKNOWN_STAGE", "The stage name isn't known. Stages are named like in the TYPE instruction (VERTEX, FRAGMENT, GEOMETRY; case-insensitive for flags and front matter); run `ve_shader --list-stages` for all of them."),
+    ("E_UNKNOWN_VARIANT", "--variants names a variant without a [variant.<name>] table in the config file."),
+    ("E_ARCHIVE_WRITE", "The archive passed with --archive couldn't be written. Check that its directory exists and is writable."),
+    ("E_MANIFEST_WRITE", "The manifest passed with --manifest couldn't be written. Check that its directory exists and is writable."),
+    ("E_TRACE_WRITE", "The trace passed with --trace-timing couldn't be written. Check that its directory exists and is writable."),
+    ("E_COMPILE_DB_WRITE", "compile_commands.json couldn't be written to the output directory. Check that it is writable."),
+    ("E_REPORT_WRITE", "The report passed with --report-json couldn't be written. Check that its directory exists and is writable, or pass `-` for stdout."),
+    ("E_INTERRUPTED", "The run was interrupted with Ctrl-C. The shaders compiled until then were written."),
+    ("E_FILE_READ", "A shader couldn't be read, or isn't valid UTF-8."),
+    ("E_COMPILATION", "shaderc reported an error in the shader; the message has the file and line. For includes that aren't found, check -I, --base-include-dir and --include-mode: `\"...\"` is resolved next to the including file and `<...>` in the include directories by default."),
+    ("E_INVALID_INSTRUCTION", "A //# instruction has an invalid argument, e.g. VERSION needs a bare number like 450."),
+    ("E_MISSING_INSTRUCTION", "--strict requires TYPE and VERSION instructions in every shader, instead of inferring them."),
+    ("E_NON_UNICODE_PATH", "shaderc takes file names as UTF-8, so shaders in paths that aren't valid UTF-8 can't be compiled. Rename the file or directory."),
+    ("E_INVALID_DIRECTORY_CONFIG", "A veshader.toml in the directory of a shader or above isn't valid; its keys before the first table accept the same settings as a profile: `defines`, `target-version`, `target-env`, `spirv-version` and `optimization`."),
+    ("E_INVALID_SIDECAR", "The sidecar file (<shader>.toml) isn't valid; it accepts `entry-point` (a string) and `defines` (an array of strings)."),
+    ("E_INVALID_FRONT_MATTER", "The front matter between the //--- lines isn't valid; it accepts `stage`, `version`, `entry` and `defines` as `// key: value` lines."),
+    ("E_POST_PROCESS", "The --post-process command failed or couldn't be started. Run it by hand on the .spv file to see its output."),
+    ("E_LINK", "spirv-link from SPIRV-Tools failed or couldn't be started. Check that it is on the PATH; the message has its output."),
+    ("E_VALIDATION", "A check of --validate failed, e.g. the compiled geometry shader couldn't be read."),
+    ("E_TOO_LARGE", "The SPIR-V binary is larger than --max-size allows. Try -O size, or split the shader."),
+];
+
+thread_local! {
+    /// Code of the error that is being logged, for the JSON log format
+    static ERROR_CODE: Cell<Option<&'static str>> = const { Cell::new(None) };
+}
+
+/// Logs an error; the JSON log format adds its code in a `code` field
+fn log_error(code: &'static str, err: &dyn std::fmt::Display) {
+    ERROR_CODE.with(|current| current.set(Some(code)));
+    error!("{}", err);
+    ERROR_CODE.with(|current| current.set(None));
 }
 
 const GLOB_OPTIONS: glob::MatchOptions = glob::MatchOptions {
@@ -113,303 +517,4645 @@ const GLOB_OPTIONS: glob::MatchOptions = glob::MatchOptions {
     require_literal_leading_dot: false,
 };
 
+/// Lists globs of files that are never compiled, like a .gitignore
+const IGNORE_FILE: &str = ".veshaderignore";
+
+/// Config file that is used if none is given
+const DEFAULT_CONFIG_FILE: &str = "veshader.toml";
+/// Written to the output directory with --emit-compile-db
+const COMPILE_DB_FILE: &str = "compile_commands.json";
+
+/// How often watch mode checks the shaders for changes
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
 fn main() {
     let args = CliArgs::from_args();
 
-    if !args.verbose {
-        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+    let mut logger = if !args.verbose {
+        // watch mode is interactive, so also show progress
+        let default_filter = match args.command {
+            Some(Command::Watch { .. }) => "info",
+            _ => "warn",
+        };
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default_filter))
     } else {
-        env_logger::Builder::new()
-            .filter(None, log::LevelFilter::Debug)
-            .init();
+        let mut logger = env_logger::Builder::new();
+        logger.filter(None, log::LevelFilter::Debug);
+        logger
+    };
+    // one JSON object per record, for log aggregators
+    if args.log_format.unwrap_or_default() == LogFormat::Json {
+        logger.format(|buf, record| {
+            let code = ERROR_CODE.with(Cell::get).map_or_else(String::new, |code| {
+                format!(",\"code\":{}", json::string(code))
+            });
+            writeln!(
+                buf,
+                "{{\"timestamp\":{},\"level\":{},\"target\":{},\"message\":{}{}}}",
+                json::string(&buf.timestamp().to_string()),
+                json::string(record.level().as_str()),
+                json::string(record.target()),
+                json::string(&record.args().to_string()),
+                code
+            )
+        });
     }
+    logger.init();
 
     if args.rick {
         info!("https://www.youtube.com/watch?v=dQw4w9WgXcQ");
         exit(0);
     }
 
-    if let Err(err) = prepare(args) {
-        error!("{}", err);
+    if let Some(code) = &args.explain {
+        match EXPLANATIONS.iter().find(|(other, _)| *other == code) {
+            Some((_, explanation)) => println!("{}: {}", code, explanation),
+            None => {
+                error!("Unknown error code: {}", code);
+                exit(1);
+            }
+        }
+        exit(0);
+    }
+
+    // print accepted values
+    if args.list_targets || args.list_stages || args.list_optimizations || args.list_presets {
+        if args.list_targets {
+            print_names(TARGET_VERSIONS);
+        }
+        if args.list_stages {
+            print_names(SHADER_KINDS);
+        }
+        if args.list_optimizations {
+            print_names(OPTIMIZATION_LEVELS);
+        }
+        if args.list_presets {
+            print_names(PRESETS);
+        }
+        exit(0);
+    }
+
+    let result = match args.command {
+        Some(Command::Compile { shared, output }) => prepare(shared, Some(output), Mode::Compile),
+        Some(Command::Watch { shared, output }) => prepare(shared, Some(output), Mode::Watch),
+        Some(Command::Check { shared }) => prepare(shared, None, Mode::Check),
+        Some(Command::Reflect { shared }) => prepare(shared, None, Mode::Reflect),
+        None => {
+            CliArgs::clap()
+                .print_help()
+                .expect("Couldn't print the help");
+            println!();
+            exit(1);
+        }
+    };
+
+    if let Err(err) = result {
+        log_error(err.code(), &err);
         exit(1);
     }
 }
 
-fn prepare(args: CliArgs) -> Result<(), CliError> {
-    let mut options = shaderc::CompileOptions::new().ok_or(CliError::CompilerCreation)?;
-
-    // debug
-    let mut debug = false;
-    if let Some(b) = args.debug {
-        debug = b;
+/// Prints the names of a lookup table, one per line
+fn print_names<T>(table: &[(&str, T)]) {
+    for (name, _) in table {
+        println!("{}", name);
     }
-    if debug {
-        options.set_generate_debug_info();
+}
+
+fn prepare(args: SharedArgs, output: Option<OutputArgs>, mode: Mode) -> Result<(), CliError> {
+    let config = load_config(args.config.as_deref())?;
+
+    let mut compiler = shaderc::Compiler::new().ok_or(CliError::CompilerCreation)?;
+
+    // profile
+    let profile = match &args.profile {
+        Some(name) => load_profile(name, &config)?,
+        None => Profile::default(),
+    };
+
+    // debug and warning budget
+    let (debug, max_warnings) =
+        release_settings(args.release, args.debug, args.max_warnings, &profile);
+
+    // macro definitions; later ones replace earlier ones of the same name
+    let mut defines: Vec<String> = Vec::new();
+    let defines_file = match &args.defines_file {
+        Some(path) => load_defines_file(path)?,
+        None => Vec::new(),
+    };
+    for define in profile
+        .defines
+        .iter()
+        .chain(&defines_file)
+        .chain(&args.defines)
+    {
+        push_define(&mut defines, define);
     }
 
+    // variants
+    let variants = args
+        .variants
+        .iter()
+        .map(|name| load_variant(name, &config))
+        .collect::<Result<Vec<_>, _>>()?;
+
     // optimization
-    options.set_optimization_level(
-        args.optimization
-            .unwrap_or(shaderc::OptimizationLevel::Performance),
-    );
+    let optimization = args
+        .optimization
+        .or_else(|| {
+            args.release
+                .then_some(shaderc::OptimizationLevel::Performance)
+        })
+        .or(profile.optimization)
+        .or(args.preset.map(|preset| preset.optimization))
+        .unwrap_or(shaderc::OptimizationLevel::Performance);
+
+    // per-stage optimization
+    let mut stage_optimization = Vec::new();
+    if let Some(table) = config.table("optimization") {
+        for (stage, level) in table {
+            let kind = parse_shader_kind(&stage.to_ascii_uppercase()).ok_or_else(|| {
+                CliError::InvalidConfigValue(format!("Unknown stage: optimization.{}", stage))
+            })?;
+            let level = level
+                .as_str()
+                .ok_or_else(|| {
+                    CliError::InvalidConfigValue(format!(
+                        "Expected a string: optimization.{}",
+                        stage
+                    ))
+                })
+                .and_then(|level| {
+                    parse_optimization_level(level).map_err(CliError::InvalidConfigValue)
+                })?;
+            stage_optimization.push((kind, level));
+        }
+    }
+
+    // stage extensions, the flag taking precedence over the config
+    let mut stage_extensions = Vec::new();
+    let mut extension_pairs: Vec<(String, String)> = Vec::new();
+    if let Some(table) = config.table("extensions") {
+        for (stage, extension) in table {
+            let extension = extension.as_str().ok_or_else(|| {
+                CliError::InvalidConfigValue(format!("Expected a string: extensions.{}", stage))
+            })?;
+            extension_pairs.push((stage.clone(), String::from(extension)));
+        }
+    }
+    if let Some(ext_map) = output.as_ref().and_then(|output| output.ext_map.as_ref()) {
+        extension_pairs.extend(ext_map.0.iter().cloned());
+    }
+    for (stage, extension) in extension_pairs {
+        let kind = parse_shader_kind(&stage.to_ascii_uppercase())
+            .ok_or_else(|| CliError::InvalidExtensionMap(format!("unknown stage {}", stage)))?;
+        stage_extensions.retain(|(other, _)| *other != kind);
+        stage_extensions.push((kind, extension));
+    }
+
+    // stages of files without TYPE instruction
+    let parse_stage = |stage: &String| {
+        parse_shader_kind(&stage.to_ascii_uppercase())
+            .ok_or_else(|| CliError::UnknownStage(stage.clone()))
+    };
+    let stage = args.stage.as_ref().map(parse_stage).transpose()?;
+    let default_stage = args.default_stage.as_ref().map(parse_stage).transpose()?;
 
     // target version
-    options.set_target_env(
-        shaderc::TargetEnv::Vulkan,
-        args.shader_version.unwrap_or_default().into_bitmask(),
-    );
+    let target_version = args
+        .shader_version
+        .or(profile.target_version)
+        .or(args.preset.map(|preset| preset.target_version))
+        .unwrap_or_default();
+
+    // output files, from --emit and the individual flags
+    let emit: &[Emit] = output.as_ref().map_or(&[], |output| output.emit.as_slice());
+
+    // target environment; the Vulkan version doesn't apply to OpenGL
+    let target_env = args
+        .target_env
+        .or(profile.target_env)
+        .unwrap_or(shaderc::TargetEnv::Vulkan);
+    if target_env != shaderc::TargetEnv::Vulkan && args.shader_version.is_some() {
+        warn!("--target-version has no effect with an OpenGL target environment");
+    }
 
-    // target environment
-    if let Some(target) = args.target {
-        options.set_forced_version_profile(target, shaderc::GlslProfile::None);
+    // source language
+    let language = args.language.unwrap_or(shaderc::SourceLanguage::GLSL);
+
+    // HLSL register shifts
+    if language != shaderc::SourceLanguage::HLSL && !args.hlsl_shifts.is_empty() {
+        warn!("Ignoring --hlsl-shift, because the source language is not HLSL.");
+    }
+    if language == shaderc::SourceLanguage::HLSL && args.target.is_some() {
+        warn!(
+            "Ignoring --target, because it forces a GLSL version and the source language is HLSL."
+        );
     }
 
     if args.ignore_extension {
         debug!("Compiling files with all file extensions.")
     }
 
-    let output_path = Path::new(&args.output);
-    // check if output folder exists
-    if !output_path.exists() && !output_path.is_dir() {
-        return Err(CliError::OutputFolderNonExistant(
-            output_path
-                .to_str()
-                .expect("Invalid output path")
-                .to_owned(),
-        ));
+    if output
+        .as_ref()
+        .is_some_and(|output| output.post_process.is_some() && output.archive.is_some())
+    {
+        warn!("Ignoring --post-process, because the outputs are written into an archive.");
+    }
+    if output
+        .as_ref()
+        .is_some_and(|output| output.link && output.archive.is_some())
+    {
+        warn!("Ignoring --link, because the outputs are written into an archive.");
+    }
+    if args.trace_timing.is_some() && mode == Mode::Watch {
+        warn!("Ignoring --trace-timing, because watch mode doesn't finish.");
+    }
+    if output
+        .as_ref()
+        .is_some_and(|output| output.emit_compile_db && mode == Mode::Watch)
+    {
+        warn!("Ignoring --emit-compile-db, because watch mode doesn't finish.");
+    }
+    if args.report_json.is_some() && mode == Mode::Watch {
+        warn!("Ignoring --report-json, because watch mode doesn't finish.");
     }
 
-    let glob = glob::glob_with(&args.glob, GLOB_OPTIONS)?;
-    for path in glob {
-        let path = path?;
+    if args.glslc_includes && (args.base_include_dir || args.include_once) {
+        warn!(
+            "Ignoring --base-include-dir and --include-once, because glslc doesn't support them."
+        );
+    }
 
-        // check extension
-        if let Some(Some(extension)) = path.extension().map(|x| x.to_str()) {
-            if extension.to_ascii_lowercase() != "glsl" && !args.ignore_extension {
-                warn!("Skipped {} because it does not have the .glsl file extension. Ignore with --ignore-extension.", path.display());
-            } else {
-                let options = options.clone().expect("Couldn't clone shader options.");
+    for dir in &args.include_dirs {
+        if !dir.is_dir() {
+            return Err(CliError::IncludeDirNonExistant(dir.display().to_string()));
+        }
+    }
 
-                info!("Compiling shader at path: {}", path.display());
-                if let Err(err) = parse(path, options, &output_path) {
-                    error!("{}", err); // handles CompilerError
-                }
+    // project root, for root-relative includes
+    let include_root = match (&args.root, &args.config) {
+        (Some(root), _) => {
+            if !root.is_dir() {
+                return Err(CliError::IncludeDirNonExistant(root.display().to_string()));
             }
-        } else {
-            warn!(
-                "Ignored file \"{}\", because no file extension was found.",
-                path.display()
-            );
+            root.clone()
         }
+        (None, Some(config)) => config.parent().map(Path::to_path_buf).unwrap_or_default(),
+        // the working directory, which holds the default config file
+        (None, None) => PathBuf::new(),
+    };
+    if args.root.is_some() && args.include_mode != Some(IncludeMode::Root) {
+        warn!("Ignoring --root, because it is only used with --include-mode root.");
     }
 
-    Ok(())
-}
+    // prelude
+    let prelude = match &args.prelude {
+        Some(path) => Some((
+            path.clone(),
+            fs::read_to_string(path).map_err(CliError::PreludeRead)?,
+        )),
+        None => None,
+    };
 
-static REG: SyncLazy<regex::Regex> = SyncLazy::new(|| regex::Regex::new(r":([0-9]*):").unwrap());
+    // output directory or archive
+    let output_target = match &output {
+        Some(OutputArgs {
+            archive: Some(archive),
+            ..
+        }) => Some(Output::Archive(archive.clone())),
+        Some(OutputArgs {
+            output: Some(output),
+            ..
+        }) => {
+            let output_path = Path::new(output);
+            // check if output folder exists
+            if !output_path.exists() && !output_path.is_dir() {
+                return Err(CliError::OutputFolderNonExistant(
+                    output_path
+                        .to_str()
+                        .expect("Invalid output path")
+                        .to_owned(),
+                ));
+            }
+            Some(Output::Directory(PathBuf::from(output)))
+        }
+        _ => None,
+    };
 
-/// Parses a shader file in the custom format
-fn parse(
-    path: PathBuf,
-    mut options: shaderc::CompileOptions,
-    output_path: &Path,
-) -> Result<(), CompilerError> {
-    let include_path = path.clone();
-    options.set_include_callback(move |name, ty, src, _depth| {
-        let path = match ty {
-            shaderc::IncludeType::Relative => Path::new(src).parent().unwrap().join(name),
-            shaderc::IncludeType::Standard => include_path.parent().unwrap().join(name),
-        };
-        let path_str = path.to_str().ok_or("Non-unicode path")?.to_owned();
-        Ok(shaderc::ResolvedInclude {
-            resolved_name: path_str,
-            content: fs::read_to_string(path).map_err(|x| x.to_string())?,
+    // flags, which take precedence over the veshader.toml of a directory
+    let explicit = Profile {
+        defines: defines_file.iter().chain(&args.defines).cloned().collect(),
+        target_version: args.shader_version,
+        target_env: args.target_env,
+        spirv_version: args.spirv_version,
+        optimization: args.optimization.or_else(|| {
+            args.release
+                .then_some(shaderc::OptimizationLevel::Performance)
+        }),
+        debug: args.debug.or_else(|| args.release.then_some(false)),
+        max_warnings: args.max_warnings,
+    };
+
+    let settings = Settings {
+        debug,
+        defines,
+        spirv_version: args
+            .spirv_version
+            .or(profile.spirv_version)
+            .or(args.preset.map(|preset| preset.spirv_version)),
+        forced_version: args.target,
+        hlsl_shifts: args.hlsl_shifts,
+        auto_bind: args.auto_bind,
+        threads_per_file: args.threads_per_file.max(1),
+        output: output_target,
+        include_dirs: args.include_dirs,
+        base_include_dir: args.base_include_dir,
+        prelude,
+        spec_constants: args.spec_constants,
+        variants,
+        naming: output
+            .as_ref()
+            .and_then(|output| output.naming)
+            .or(args.preset.map(|preset| preset.naming))
+            .unwrap_or_default(),
+        stage_extensions,
+        target_version,
+        target_env,
+        language,
+        emit_spv: !output.as_ref().is_some_and(|output| output.asm_only)
+            && (emit.is_empty() || emit.contains(&Emit::Spv)),
+        emit_asm: output
+            .as_ref()
+            .is_some_and(|output| output.emit_asm || output.asm_only)
+            || emit.contains(&Emit::Asm),
+        assembly_roundtrip: output
+            .as_ref()
+            .is_some_and(|output| output.assembly_roundtrip),
+        output_bytes_as_u32: output
+            .as_ref()
+            .is_some_and(|output| output.output_bytes_as_u32),
+        emit_hash: output.as_ref().is_some_and(|output| output.emit_hash),
+        no_binary_on_warning: output
+            .as_ref()
+            .is_some_and(|output| output.no_binary_on_warning),
+        emit_c_header: output.as_ref().is_some_and(|output| output.emit_c_header)
+            || emit.contains(&Emit::CHeader),
+        emit_metadata_header: output
+            .as_ref()
+            .is_some_and(|output| output.emit_metadata_header),
+        flatten_includes: output
+            .as_ref()
+            .is_some_and(|output| output.flatten_includes)
+            || emit.contains(&Emit::Flat),
+        source_map: output.as_ref().is_some_and(|output| output.source_map)
+            || emit.contains(&Emit::SourceMap),
+        emit_deps_json: output.as_ref().is_some_and(|output| output.emit_deps_json)
+            || emit.contains(&Emit::Deps),
+        emit_reflection: emit.contains(&Emit::Reflect),
+        emit_compile_db: output.as_ref().is_some_and(|output| output.emit_compile_db),
+        dump_on_error: args.dump_on_error,
+        post_process: output
+            .as_ref()
+            .and_then(|output| output.post_process.clone()),
+        link: output
+            .as_ref()
+            .is_some_and(|output| output.link && output.archive.is_none()),
+        ignore_extension: args.ignore_extension,
+        includes_as_dependencies: args.includes_as_dependencies,
+        sort: args.sort,
+        ignored: load_ignore_file()?,
+        strict: args.strict,
+        stage_from_dir: args.stage_from_dir,
+        stage,
+        default_stage,
+        // the names of hashed outputs aren't known before compiling
+        skip_unchanged: output
+            .as_ref()
+            // linking needs every stage of a file
+            .is_some_and(|output| {
+                output.no_op_on_no_change
+                    && !output.force
+                    && !output.clean
+                    && !output.emit_hash
+                    && !output.link
+            }),
+        force: output
+            .as_ref()
+            .is_some_and(|output| output.force || output.clean),
+        auto_version: !args.no_auto_version,
+        glslc_includes: args.glslc_includes,
+        // glslc resolves includes in the same order as gcc
+        include_mode: if args.glslc_includes {
+            IncludeMode::Gcc
+        } else {
+            args.include_mode.unwrap_or_default()
+        },
+        include_root,
+        diagnostic_level: args.diagnostic_level.unwrap_or_default(),
+        include_once: args.include_once && !args.glslc_includes,
+        optimization,
+        stage_optimization,
+        retry_on_failure: args.retry_on_failure,
+        module_info: args.module_info,
+        emit_debug_variant: output
+            .as_ref()
+            .is_some_and(|output| output.emit_debug_variant),
+        instr_count: args.instr_count,
+        validate: args.validate,
+        reflection_schema: match &args.reflection_schema {
+            Some(path) => load_schema(path)?,
+            None => Vec::new(),
+        },
+        bench: args.bench.map(|runs| runs.max(1)),
+        trace: args.trace_timing.as_ref().map(|_| Instant::now()),
+        max_size: args.max_size,
+        reflect: mode == Mode::Reflect,
+        report_to_stdout: args.report_json.as_deref() == Some("-"),
+        explicit,
+        project_root: std::env::current_dir().and_then(fs::canonicalize).ok(),
+        config_file: args
+            .config
+            .clone()
+            .or_else(|| Some(PathBuf::from(DEFAULT_CONFIG_FILE)))
+            .and_then(|path| fs::canonicalize(path).ok()),
+    };
+    if args.print_config {
+        print_config(&settings);
+        return Ok(());
+    }
+    let options = compile_options(&settings).ok_or(CliError::CompilerCreation)?;
+
+    // globs, either given directly, read from a response file or shaders named by references
+    let glob = args.glob.clone().unwrap_or_default();
+    let patterns: Vec<String> = if let Some(references) = &args.references {
+        load_references(references, language)?
+    } else if let Some(response_file) = glob.strip_prefix('@') {
+        fs::read_to_string(response_file)
+            .map_err(CliError::ResponseFileRead)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect()
+    } else {
+        vec![glob]
+    };
+    // a directory stands for all shaders in it
+    let patterns: Vec<String> = patterns
+        .iter()
+        .flat_map(|pattern| expand_braces(pattern))
+        .map(|pattern| {
+            if Path::new(&pattern).is_dir() {
+                format!(
+                    "{}/**/*.{}",
+                    pattern.trim_end_matches(&['/', '\\'][..]),
+                    source_extension(language)
+                )
+            } else {
+                pattern
+            }
         })
-    });
+        .collect();
 
-    let mut curr_shader = String::new();
-    let mut shader_type: Option<shaderc::ShaderKind> = None;
-    let mut line_mapping: Vec<usize> = Vec::new();
-    let mut version: Option<String> = None;
-    let mut added_statements = 0; // count added statements, to correct line numbers in errors
+    if args.list_includes {
+        for path in collect_shaders(&patterns, &settings, true)? {
+            print_include_tree(&path, &settings);
+        }
+        return Ok(());
+    }
 
-    if let Ok(file) = File::open(&path) {
-        // read line-by-line
-        for (idx, line) in BufReader::new(file).lines().enumerate() {
-            if let Ok(line) = line {
-                // custom format intsruction
-                if line.contains("//#") {
-                    let split: Vec<_> = line.split(' ').collect();
-                    // parse custom instructions
-                    if let Some(&instruction) = split.get(1) {
-                        // handle TYPE instruction
-                        if instruction.contains("TYPE") {
-                            // parse instruction arguments
-                            if let Some(&token) = split.get(2) {
-                                let new_kind = parse_shader_kind(token).ok_or_else(|| {
-                                    CompilerError::UnknownShaderType(String::from(token))
-                                })?;
-                                if let Some(kind) = shader_type {
-                                    compile_shader(
-                                        &curr_shader,
-                                        &path,
-                                        &options,
-                                        kind,
-                                        line_mapping,
-                                        &output_path,
-                                        &version,
-                                        &mut added_statements,
-                                    )?;
-
-                                    curr_shader = String::new();
-                                    line_mapping = Vec::new();
-                                }
-                                shader_type = Some(new_kind);
-                            }
-                        } else if instruction.contains("VERSION") && split.len() >= 3 {
-                            version = Some(String::from(split[2]));
-                        }
-                    }
-                } else if curr_shader.is_empty() {
-                    curr_shader = line;
-                    line_mapping.push(idx + 1);
-                } else {
-                    // ignore empty lines and comments
-                    if !line.is_empty() && !line.starts_with("//") {
-                        curr_shader = format!("{}\n{}", &curr_shader, &line);
-                        line_mapping.push(idx + 1);
-                    }
-                }
+    // outputs of the previous run
+    if let Some(output) = output.as_ref().filter(|output| output.clean) {
+        match (&output.manifest, &settings.output) {
+            (Some(manifest), Some(Output::Directory(output_dir))) => {
+                clean_outputs(manifest, output_dir)
             }
+            _ => warn!(
+                "--clean only deletes the outputs listed in the manifest of an output directory; pass --manifest"
+            ),
         }
     }
 
-    // compile last shader
-    if let Some(kind) = shader_type {
-        compile_shader(
-            &curr_shader,
-            &path,
-            &options,
-            kind,
-            line_mapping,
-            &output_path,
-            &version,
-            &mut added_statements,
-        )?;
+    if mode == Mode::Watch {
+        return watch(&patterns, &mut compiler, &options, &settings);
     }
-    Ok(())
-}
 
-/// Compiles a single shader
-#[allow(clippy::too_many_arguments)]
-fn compile_shader(
-    curr_shader: &str,
-    path: &Path,
-    options: &shaderc::CompileOptions,
-    kind: shaderc::ShaderKind,
-    line_mapping: Vec<usize>,
-    output_path: &Path,
-    version: &Option<String>,
-    added_statements: &mut usize,
-) -> Result<(), CompilerError> {
-    // add version to curr_shader
-    let curr_shader: String = if let Some(version) = version {
-        *added_statements += 1;
-        format!("#version {}\n{}", version, curr_shader)
-    } else {
-        String::from(curr_shader)
-    };
+    // stop between shaders on Ctrl-C, keeping the results so far
+    interrupt::install();
 
-    debug!("Compiling:\n{}", &curr_shader);
+    let mut stats = Stats::default();
+    let paths = collect_shaders(&patterns, &settings, true)?;
+    let total = paths.len();
+    for (done, path) in paths.into_iter().enumerate() {
+        if interrupt::interrupted() {
+            warn!("Interrupted after {} of {} shaders.", done, total);
+            break;
+        }
+        let options = options.clone().expect("Couldn't clone shader options.");
 
-    // compile
-    let mut compiler = shaderc::Compiler::new().unwrap();
-    let out = compiler
-        .compile_into_spirv(
-            &curr_shader,
-            kind,
-            &path.to_str().unwrap(),
-            "main",
-            Some(&options),
-        )
-        .map_err(|e| {
-            // replaces error lines from what the parser saw to what is actually used in the input file
-            let err = e.to_string();
-            let captures = REG
-                .captures(&err)
-                .expect("Failed error translation: regex failed");
-            let first_capture = captures
-                .get(1)
-                .expect("Failed error translation: no capture found")
-                .as_str();
-
-            let old_line: usize = first_capture.parse().unwrap_or_else(|_| {
-                panic!(
-                    "Failed error translation: capture not usize: {}",
-                    first_capture
-                )
-            });
+        info!("Compiling shader at path: {}", path.display());
+        let started = Instant::now();
+        let name = path.display().to_string();
+        if let Err(err) = parse(path, &mut compiler, options, &settings, &mut stats) {
+            log_error(err.code(), &err); // handles CompilerError
+            stats
+                .failed
+                .push((PathBuf::from(&name), err.code(), err.to_string()));
+        }
+        record_span(&settings, &mut stats, name, "file", started);
+    }
 
-            CompilerError::Compilation(str::replace(
-                &e.to_string(),
-                &format!(":{}:", old_line),
-                &format!(
-                    ":{}:",
-                    line_mapping
-                        .get(old_line - *added_statements)
-                        .unwrap_or_else(|| panic!(
-                            "Failed error translation: couldn't find line mapping: {}",
-                            old_line
-                        ))
-                ),
-            ))
-        })?;
+    if settings.emit_compile_db {
+        let db = compile_db(&stats.compile_commands);
+        match &settings.output {
+            Some(Output::Directory(output_dir)) => {
+                fs::write(output_dir.join(COMPILE_DB_FILE), db)
+                    .map_err(CliError::CompileDbWrite)?;
+            }
+            Some(Output::Archive(_)) => {
+                stats
+                    .archived
+                    .insert(String::from(COMPILE_DB_FILE), db.into_bytes());
+            }
+            None => {}
+        }
+    }
+    write_archive(&settings, &stats)?;
+    for constant in &settings.spec_constants {
+        if !stats.specialized.contains(&constant.id) {
+            warn!(
+                "--spec-constant {}={} didn't match a `layout(constant_id = {}) const` declaration on a single line of any shader",
+                constant.id, constant.value, constant.id
+            );
+        }
+    }
+    print_warning_summary(&stats);
+    if settings.bench.is_some() {
+        print_bench(&stats.bench, &settings);
+    }
+    if let Some(path) = output.as_ref().and_then(|output| output.manifest.as_ref()) {
+        fs::write(path, manifest(&stats)).map_err(CliError::ManifestWrite)?;
+    }
+    if let Some(path) = &args.trace_timing {
+        fs::write(path, chrome_trace(&stats.spans)).map_err(CliError::TraceWrite)?;
+    }
+    match args.report_json.as_deref() {
+        Some("-") => print!("{}", report(&stats)),
+        Some(path) => fs::write(path, report(&stats)).map_err(CliError::ReportWrite)?,
+        None => {}
+    }
+    if settings.instr_count {
+        output!(
+            settings,
+            "Total of {} shaders: {}",
+            stats.counted_shaders,
+            instruction_count(stats.instructions)
+        );
+    }
+    if interrupt::interrupted() {
+        return Err(CliError::Interrupted);
+    }
 
-    if out.get_num_warnings() != 0 {
-        warn!("{}", out.get_warning_messages());
+    // guard against misconfigured runs that silently produce nothing
+    if output
+        .as_ref()
+        .is_some_and(|output| output.fail_on_empty_output)
+        && stats.written.is_empty()
+    {
+        return Err(CliError::EmptyOutput(total));
+    }
+
+    if !stats.withheld.is_empty() {
+        return Err(CliError::WithheldBinaries(stats.withheld.len()));
+    }
+
+    // warning budget
+    if let Some(max_warnings) = max_warnings {
+        if stats.warnings > max_warnings {
+            return Err(CliError::TooManyWarnings(stats.warnings, max_warnings));
+        }
     }
 
-    // save CompliationArtifact
-    let output_folder = path.file_stem().expect("Invalid path").to_str().unwrap();
-    let output_extension = get_shader_kind_extension(kind).expect("Invalid output file extension");
-    let p = output_path.join(format!("{}-{}.spv", output_folder, output_extension));
-    std::fs::write(p, out.as_binary_u8()).expect("Unable to write file");
     Ok(())
 }
 
-/// Converts a &str to shaderc::ShaderKind
-pub fn parse_shader_kind(identifier: &str) -> Option<shaderc::ShaderKind> {
-    use shaderc::ShaderKind::*;
-    Some(match identifier {
-        "VERTEX" => Vertex,
-        "FRAGMENT" => Fragment,
-        "GEOMETRY" => Geometry,
-        _ => {
-            return None;
-        }
-    })
+/// Prints the min, median and max compile time of each shader as a table
+fn print_bench(bench: &[(String, Vec<Duration>)], settings: &Settings) {
+    let width = bench
+        .iter()
+        .map(|(label, _)| label.len())
+        .chain(std::iter::once("shader".len()))
+        .max()
+        .unwrap_or_default();
+    let ms = |duration: Duration| format!("{:.2}", duration.as_secs_f64() * 1000.0);
+
+    output!(
+        settings,
+        "{:width$}  {:>11}  {:>11}  {:>11}",
+        "shader",
+        "min (ms)",
+        "median (ms)",
+        "max (ms)",
+        width = width
+    );
+    for (label, times) in bench {
+        let mut times = times.clone();
+        times.sort();
+        output!(
+            settings,
+            "{:width$}  {:>11}  {:>11}  {:>11}",
+            label,
+            ms(times[0]),
+            ms(times[times.len() / 2]),
+            ms(times[times.len() - 1]),
+            width = width
+        );
+    }
 }
 
-/// Converts a &str to shaderc::OptimizationLevel
-fn parse_optimization_level(level: &str) -> Result<shaderc::OptimizationLevel, String> {
-    use shaderc::OptimizationLevel::*;
-    match level {
-        "zero" => Ok(Zero),
-        "size" => Ok(Size),
-        "performance" => Ok(Performance),
-        _ => Err(format!("Failed to parse optimization level: {}", level)),
+/// Lists the shaders with warnings at the end of a run
+fn print_warning_summary(stats: &Stats) {
+    if stats.warnings == 0 {
+        return;
     }
+    let shaders: Vec<_> = stats
+        .compiled
+        .iter()
+        .filter(|shader| !shader.warnings.is_empty())
+        .map(|shader| {
+            format!(
+                "{} ({}): {}",
+                shader.path.display(),
+                shader.stage,
+                shader.warnings.len()
+            )
+        })
+        .collect();
+    warn!(
+        "{} warnings in {} of {} shaders: {}",
+        stats.warnings,
+        shaders.len(),
+        stats.compiled.len(),
+        shaders.join(", ")
+    );
 }
 
-/// Converts a &str to shaderc::ShaderKind
-pub fn get_shader_kind_extension(kind: shaderc::ShaderKind) -> Option<String> {
-    use shaderc::ShaderKind::*;
-    Some(match kind {
-        Vertex => String::from("vert"),
-        Fragment => String::from("frag"),
-        Geometry => String::from("geo"),
-        _ => {
-            return None;
+/// Records the time spent on a file or stage since `started`, with --trace-timing
+fn record_span(
+    settings: &Settings,
+    stats: &mut Stats,
+    name: String,
+    category: &'static str,
+    started: Instant,
+) {
+    if let Some(origin) = settings.trace {
+        stats.spans.push(Span {
+            name,
+            category,
+            start: started.duration_since(origin),
+            duration: started.elapsed(),
+            thread: 0,
+        });
+    }
+}
+
+/// Generates a trace in the Chrome trace event format, with one complete event per span
+fn chrome_trace(spans: &[Span]) -> String {
+    let events: Vec<_> = spans
+        .iter()
+        .map(|span| {
+            format!(
+                "    {{\"name\": {}, \"cat\": {}, \"ph\": \"X\", \"ts\": {}, \"dur\": {}, \"pid\": 1, \"tid\": {}}}",
+                json::string(&span.name),
+                json::string(span.category),
+                span.start.as_micros(),
+                span.duration.as_micros(),
+                span.thread
+            )
+        })
+        .collect();
+    format!(
+        "{{\n  \"traceEvents\": [\n{}\n  ],\n  \"displayTimeUnit\": \"ms\"\n}}\n",
+        events.join(",\n")
+    )
+}
+
+/// Generates the JSON manifest of a run
+fn manifest(stats: &Stats) -> String {
+    let mut outputs = stats.written.clone();
+    outputs.sort();
+    let outputs: Vec<_> = outputs.iter().map(|name| json::string(name)).collect();
+
+    let mut compiled: Vec<_> = stats.compiled.iter().collect();
+    compiled.sort_by(|a, b| (&a.path, &a.stage, &a.variant).cmp(&(&b.path, &b.stage, &b.variant)));
+    let shaders: Vec<_> = compiled
+        .iter()
+        .map(|shader| {
+            let warnings: Vec<_> = shader
+                .warnings
+                .iter()
+                .map(|warning| json::string(warning))
+                .collect();
+            format!(
+                "    {{\"source\": {}, \"stage\": {}, \"variant\": {}, \"warnings\": [{}]}}",
+                json::string(&shader.path.display().to_string()),
+                json::string(&shader.stage),
+                shader
+                    .variant
+                    .as_deref()
+                    .map_or_else(|| String::from("null"), json::string),
+                warnings.join(", ")
+            )
+        })
+        .collect();
+    let hashed: Vec<_> = stats
+        .hashed
+        .iter()
+        .map(|(name, hashed)| format!("{}: {}", json::string(name), json::string(hashed)))
+        .collect();
+    format!(
+        "{{\n  \"warnings\": {},\n  \"outputs\": [{}],\n  \"hashed\": {{{}}},\n  \"shaders\": [\n{}\n  ]\n}}\n",
+        stats.warnings,
+        outputs.join(", "),
+        hashed.join(", "),
+        shaders.join(",\n")
+    )
+}
+
+/// Generates compile_commands.json: one entry per stage of each file, relative to the working
+/// directory
+fn compile_db(commands: &[CompileCommand]) -> String {
+    let directory = std::env::current_dir().unwrap_or_default();
+    let entries: Vec<_> = commands
+        .iter()
+        .map(|command| {
+            let arguments: Vec<_> = command
+                .arguments
+                .iter()
+                .map(|argument| json::string(argument))
+                .collect();
+            format!(
+                "  {{\"directory\": {}, \"file\": {}, \"arguments\": [{}]}}",
+                json::string(&directory.display().to_string()),
+                json::string(&command.path.display().to_string()),
+                arguments.join(", ")
+            )
+        })
+        .collect();
+    format!("[\n{}\n]\n", entries.join(",\n"))
+}
+
+/// Deletes the output files a manifest lists, and the manifest
+///
+/// Names that would leave the output directory are skipped, as is a manifest that doesn't exist
+/// yet or can't be parsed.
+fn clean_outputs(manifest: &Path, output_dir: &Path) {
+    let source = match fs::read_to_string(manifest) {
+        Ok(source) => source,
+        Err(_) => return,
+    };
+    let document = match json::parse(&source) {
+        Ok(document) => document,
+        Err(err) => {
+            warn!(
+                "Not cleaning, the manifest {} is invalid: {}",
+                manifest.display(),
+                err
+            );
+            return;
         }
-    })
+    };
+    let outputs = match &document {
+        json::Value::Object(members) => members
+            .iter()
+            .filter(|(key, _)| key == "outputs")
+            .flat_map(|(_, outputs)| outputs.strings())
+            .collect(),
+        _ => Vec::new(),
+    };
+    let mut removed = 0;
+    for name in outputs {
+        let inside = Path::new(name)
+            .components()
+            .all(|component| matches!(component, std::path::Component::Normal(_)));
+        if !inside {
+            warn!(
+                "Not deleting {}, because it is outside of the output directory",
+                name
+            );
+            continue;
+        }
+        match fs::remove_file(output_dir.join(name)) {
+            Ok(()) => removed += 1,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => warn!("Couldn't delete {}: {}", name, err),
+        }
+    }
+    if let Err(err) = fs::remove_file(manifest) {
+        warn!("Couldn't delete {}: {}", manifest.display(), err);
+    }
+    info!("Deleted {} outputs of the previous run", removed);
+}
+
+/// Generates the JSON report of a run: every compiled shader with its outputs, and every failure
+fn report(stats: &Stats) -> String {
+    let strings = |items: &[String]| {
+        items
+            .iter()
+            .map(|item| json::string(item))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let shaders: Vec<_> = stats
+        .compiled
+        .iter()
+        .map(|shader| {
+            format!(
+                "    {{\"source\": {}, \"stage\": {}, \"variant\": {}, \"size\": {}, \"outputs\": [{}], \"warnings\": [{}]}}",
+                json::string(&shader.path.display().to_string()),
+                json::string(&shader.stage),
+                shader
+                    .variant
+                    .as_deref()
+                    .map_or_else(|| String::from("null"), json::string),
+                shader
+                    .size
+                    .map_or_else(|| String::from("null"), |size| size.to_string()),
+                strings(&shader.outputs),
+                strings(&shader.warnings)
+            )
+        })
+        .collect();
+    let failed: Vec<_> = stats
+        .failed
+        .iter()
+        .map(|(path, code, message)| {
+            format!(
+                "    {{\"source\": {}, \"code\": {}, \"message\": {}}}",
+                json::string(&path.display().to_string()),
+                json::string(code),
+                json::string(message)
+            )
+        })
+        .collect();
+    format!(
+        "{{\n  \"success\": {},\n  \"warnings\": {},\n  \"shaders\": [\n{}\n  ],\n  \"failed\": [\n{}\n  ]\n}}\n",
+        stats.failed.is_empty(),
+        stats.warnings,
+        shaders.join(",\n"),
+        failed.join(",\n")
+    )
+}
+
+/// Prints the settings a run would use, one per line
+fn print_config(settings: &Settings) {
+    let list = |items: Vec<String>| {
+        if items.is_empty() {
+            String::from("-")
+        } else {
+            items.join(", ")
+        }
+    };
+    let stage = |kind: shaderc::ShaderKind| get_shader_kind_extension(kind).unwrap_or_default();
+
+    println!("language: {}", source_extension(settings.language));
+    println!(
+        "target version: {}",
+        table_name(TARGET_VERSIONS, settings.target_version)
+    );
+    println!(
+        "target environment: {}",
+        table_name(TARGET_ENVS, settings.target_env)
+    );
+    println!(
+        "spirv version: {}",
+        settings
+            .spirv_version
+            .map_or("default of the target version", |version| {
+                table_name(SPIRV_VERSIONS, version)
+            })
+    );
+    println!(
+        "forced glsl version: {}",
+        settings
+            .forced_version
+            .map_or_else(|| String::from("-"), |version| version.to_string())
+    );
+    println!(
+        "optimization: {}",
+        table_name(OPTIMIZATION_LEVELS, settings.optimization)
+    );
+    for (kind, level) in &settings.stage_optimization {
+        println!(
+            "optimization of {}: {}",
+            stage(*kind),
+            table_name(OPTIMIZATION_LEVELS, *level)
+        );
+    }
+    println!("debug info: {}", settings.debug);
+    println!(
+        "diagnostic level: {}",
+        match settings.diagnostic_level {
+            DiagnosticLevel::Error => "error",
+            DiagnosticLevel::Warning => "warning",
+            DiagnosticLevel::Note => "note",
+        }
+    );
+    println!("defines: {}", list(settings.defines.clone()));
+    println!(
+        "variants: {}",
+        list(
+            settings
+                .variants
+                .iter()
+                .map(|variant| format!("{} [{}]", variant.name, variant.defines.join(", ")))
+                .collect()
+        )
+    );
+    println!(
+        "spec constants: {}",
+        list(
+            settings
+                .spec_constants
+                .iter()
+                .map(|constant| format!("{}={}", constant.id, constant.value))
+                .collect()
+        )
+    );
+    println!(
+        "include mode: {}",
+        match settings.include_mode {
+            IncludeMode::QuoteRelative => "quote-relative",
+            IncludeMode::AllSearch => "all-search",
+            IncludeMode::Gcc => "gcc",
+            IncludeMode::Root => "root",
+        }
+    );
+    if settings.include_mode == IncludeMode::Root {
+        println!("include root: {}", settings.include_root.display());
+    }
+    println!(
+        "include directories: {}",
+        list(
+            settings
+                .include_dirs
+                .iter()
+                .map(|dir| dir.display().to_string())
+                .collect()
+        )
+    );
+    println!("base include dir: {}", settings.base_include_dir);
+    println!("include once: {}", settings.include_once);
+    println!("prelude: {}", settings.prelude.is_some());
+    println!(
+        "output: {}",
+        match &settings.output {
+            Some(Output::Directory(path)) => format!("directory {}", path.display()),
+            Some(Output::Archive(path)) => format!("archive {}", path.display()),
+            None => String::from("-"),
+        }
+    );
+    println!(
+        "naming: {}",
+        settings
+            .naming
+            .file_name("foo", &stage(shaderc::ShaderKind::Fragment), "spv")
+    );
+    println!(
+        "stage names: {}",
+        list(
+            settings
+                .stage_extensions
+                .iter()
+                .map(|(kind, extension)| format!("{}={}", stage(*kind), extension))
+                .collect()
+        )
+    );
+}
+
+/// Name of a value in a lookup table
+fn table_name<T: PartialEq>(table: &[(&'static str, T)], value: T) -> &'static str {
+    table
+        .iter()
+        .find(|(_, other)| *other == value)
+        .map_or("unknown", |(name, _)| name)
+}
+
+/// Expands `{a,b}` alternatives like a shell, since the glob crate doesn't support them
+///
+/// Every brace pair multiplies the patterns: `*.{vert,frag}.{glsl,hlsl}` becomes four patterns,
+/// and nested pairs expand within their alternative. As in a shell, braces without a comma
+/// (`{a}`) and unbalanced braces are left as they are.
+fn expand_braces(pattern: &str) -> Vec<String> {
+    let open = match pattern.find('{') {
+        Some(open) => open,
+        None => return vec![String::from(pattern)],
+    };
+
+    // the matching brace, and the commas between them that separate the alternatives
+    let mut depth = 0;
+    let mut separators = Vec::new();
+    let mut close = None;
+    for (idx, c) in pattern.char_indices().skip_while(|(idx, _)| *idx < open) {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    close = Some(idx);
+                    break;
+                }
+            }
+            ',' if depth == 1 => separators.push(idx),
+            _ => {}
+        }
+    }
+    let close = match close {
+        Some(close) => close,
+        None => {
+            debug!("Not expanding the unbalanced braces of {}", pattern);
+            return vec![String::from(pattern)];
+        }
+    };
+
+    let (prefix, suffix) = (&pattern[..open], &pattern[close + 1..]);
+    if separators.is_empty() {
+        let group = &pattern[..=close];
+        return expand_braces(suffix)
+            .into_iter()
+            .map(|suffix| format!("{}{}", group, suffix))
+            .collect();
+    }
+    let mut start = open + 1;
+    let mut alternatives = Vec::new();
+    for end in separators.into_iter().chain(std::iter::once(close)) {
+        alternatives.push(&pattern[start..end]);
+        start = end + 1;
+    }
+    alternatives
+        .into_iter()
+        .flat_map(|alternative| expand_braces(&format!("{}{}{}", prefix, alternative, suffix)))
+        .collect()
+}
+
+/// Writes the collected output files, if they go into an archive
+fn write_archive(settings: &Settings, stats: &Stats) -> Result<(), CliError> {
+    if let Some(Output::Archive(path)) = &settings.output {
+        archive::write_zip(path, &stats.archived).map_err(CliError::ArchiveWrite)?;
+        info!(
+            "Wrote {} files to the archive {}",
+            stats.archived.len(),
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Creates the compile options of a run
+///
+/// Workers compiling stages in parallel need their own options, since shaderc's aren't `Send`.
+fn compile_options(settings: &Settings) -> Option<shaderc::CompileOptions<'static>> {
+    let mut options = shaderc::CompileOptions::new()?;
+
+    if settings.debug {
+        options.set_generate_debug_info();
+    }
+    for define in &settings.defines {
+        add_define(&mut options, define);
+    }
+    options.set_optimization_level(settings.optimization);
+    let env_version = match settings.target_env {
+        shaderc::TargetEnv::Vulkan => settings.target_version.into_bitmask(),
+        // OpenGL 4.5, the only version glslang accepts
+        _ => shaderc::EnvVersion::OpenGL4_5 as u32,
+    };
+    options.set_target_env(settings.target_env, env_version);
+    if let Some(spirv_version) = settings.spirv_version {
+        options.set_target_spirv(spirv_version);
+    }
+    // target environment; GLSL profiles don't apply to HLSL
+    if let Some(target) = settings.forced_version {
+        if settings.language == shaderc::SourceLanguage::GLSL {
+            options.set_forced_version_profile(target, shaderc::GlslProfile::None);
+        }
+    }
+    options.set_source_language(settings.language);
+    if settings.language == shaderc::SourceLanguage::HLSL {
+        for shift in &settings.hlsl_shifts {
+            options.set_hlsl_register_set_and_binding(&shift.register, &shift.set, &shift.binding);
+        }
+    }
+    // automatic binding assignment
+    if settings.auto_bind {
+        options.set_auto_bind_uniforms(true);
+    }
+    Some(options)
+}
+
+/// Loads the given config file, or the default one if it exists
+fn load_config(path: Option<&Path>) -> Result<Config, CliError> {
+    let path = match path {
+        Some(path) => path,
+        None if Path::new(DEFAULT_CONFIG_FILE).is_file() => Path::new(DEFAULT_CONFIG_FILE),
+        None => return Ok(Config::default()),
+    };
+    debug!("Using config file: {}", path.display());
+    let source = fs::read_to_string(path).map_err(CliError::ConfigRead)?;
+    Ok(Config::parse(&source)?)
+}
+
+/// Adds a `NAME` or `NAME=VALUE` macro definition
+fn add_define(options: &mut shaderc::CompileOptions, define: &str) {
+    match define.split_once('=') {
+        Some((name, value)) => options.add_macro_definition(name, Some(value)),
+        None => options.add_macro_definition(define, None),
+    }
+}
+
+/// Adds a `NAME` or `NAME=VALUE` macro definition to a list, replacing an earlier one of the same name
+fn push_define(defines: &mut Vec<String>, define: &str) {
+    let name = define.split('=').next().unwrap_or_default();
+    defines.retain(|other| other.split('=').next().unwrap_or_default() != name);
+    defines.push(String::from(define));
+}
+
+/// Reads `NAME=VALUE` (or `NAME`) lines of a defines file; lines starting with `#` are comments
+fn load_defines_file(path: &Path) -> Result<Vec<String>, CliError> {
+    let source = fs::read_to_string(path).map_err(CliError::DefinesFileRead)?;
+    let mut defines = Vec::new();
+    for (idx, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let name = line.split('=').next().unwrap_or_default().trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(CliError::InvalidDefinesFile(format!(
+                "line {}: invalid macro name: {}",
+                idx + 1,
+                name
+            )));
+        }
+        defines.push(match line.split_once('=') {
+            Some((_, value)) => format!("{}={}", name, value.trim()),
+            None => String::from(name),
+        });
+    }
+    Ok(defines)
+}
+
+/// Reads the descriptor bindings each stage requires from a schema file
+///
+/// Every table is named after a stage and lists `"<set>:<binding>:<type>"` in `bindings`, where
+/// the type (e.g. `uniform_buffer`) may be left out to accept any.
+fn load_schema(path: &Path) -> Result<Vec<RequiredBinding>, CliError> {
+    let source = fs::read_to_string(path).map_err(CliError::SchemaRead)?;
+    let schema = Config::parse(&source).map_err(|err| CliError::InvalidSchema(err.to_string()))?;
+    let mut required = Vec::new();
+    for (stage, table) in schema.tables() {
+        let kind = parse_shader_kind(&stage.to_ascii_uppercase())
+            .ok_or_else(|| CliError::InvalidSchema(format!("Unknown stage: {}", stage)))?;
+        for (key, value) in table {
+            let bindings = match (key.as_str(), value) {
+                ("bindings", config::Value::Array(bindings)) => bindings,
+                _ => {
+                    return Err(CliError::InvalidSchema(format!(
+                        "Expected an array of bindings: {}.{}",
+                        stage, key
+                    )))
+                }
+            };
+            for binding in bindings {
+                let invalid = || {
+                    CliError::InvalidSchema(format!(
+                        "Expected \"<set>:<binding>:<type>\": {}.bindings",
+                        stage
+                    ))
+                };
+                let mut parts = binding.as_str().ok_or_else(invalid)?.split(':');
+                let mut number = || parts.next().and_then(|part| part.trim().parse().ok());
+                let (set, binding) = (number().ok_or_else(invalid)?, number().ok_or_else(invalid)?);
+                let descriptor = match parts.next() {
+                    Some(descriptor) => Some(descriptor.trim().parse().map_err(|err| {
+                        CliError::InvalidSchema(format!("{}: {}.bindings", err, stage))
+                    })?),
+                    None => None,
+                };
+                required.push(RequiredBinding {
+                    kind,
+                    set,
+                    binding,
+                    descriptor,
+                });
+            }
+        }
+    }
+    Ok(required)
+}
+
+/// Resolves debug info and the warning budget
+///
+/// `--release` ignores what a profile says about either, so only `-d` and `--max-warnings` given
+/// along with it still apply.
+fn release_settings(
+    release: bool,
+    debug: Option<bool>,
+    max_warnings: Option<u32>,
+    profile: &Profile,
+) -> (bool, Option<u32>) {
+    if release {
+        return (debug.unwrap_or(false), max_warnings);
+    }
+    (
+        debug.or(profile.debug).unwrap_or(false),
+        max_warnings.or(profile.max_warnings),
+    )
+}
+
+/// Looks up a profile in the config file, falling back to the built-in profiles
+fn load_profile(name: &str, config: &Config) -> Result<Profile, CliError> {
+    let table = match config.table(&format!("profile.{}", name)) {
+        Some(table) => table,
+        None => {
+            return Profile::builtin(name)
+                .ok_or_else(|| CliError::UnknownProfile(String::from(name)))
+        }
+    };
+    debug!("Using profile {} of the config file", name);
+    parse_profile(table, &format!("profile.{}.", name))
+}
+
+/// Reads the settings of a profile table, whose keys are named `<prefix><key>` in errors
+fn parse_profile(table: &config::Table, prefix: &str) -> Result<Profile, CliError> {
+    let invalid = |key: &str, expected: &str| {
+        CliError::InvalidConfigValue(format!("Expected {}: {}{}", expected, prefix, key))
+    };
+    let mut profile = Profile::default();
+    for (key, value) in table {
+        match key.as_str() {
+            "defines" => match value {
+                config::Value::Array(defines) => {
+                    for define in defines {
+                        let define = define.as_str().ok_or_else(|| invalid(key, "strings"))?;
+                        profile.defines.push(String::from(define));
+                    }
+                }
+                _ => return Err(invalid(key, "an array")),
+            },
+            "target-version" => {
+                let version = value.as_str().ok_or_else(|| invalid(key, "a string"))?;
+                profile.target_version = Some(version.parse()?);
+            }
+            "target-env" => {
+                let env = value.as_str().ok_or_else(|| invalid(key, "a string"))?;
+                profile.target_env =
+                    Some(parse_target_env(env).map_err(CliError::InvalidConfigValue)?);
+            }
+            "spirv-version" => {
+                let version = value.as_str().ok_or_else(|| invalid(key, "a string"))?;
+                profile.spirv_version =
+                    Some(parse_spirv_version(version).map_err(CliError::InvalidConfigValue)?);
+            }
+            "optimization" => {
+                let level = value.as_str().ok_or_else(|| invalid(key, "a string"))?;
+                profile.optimization =
+                    Some(parse_optimization_level(level).map_err(CliError::InvalidConfigValue)?);
+            }
+            "debug" => match value {
+                config::Value::Boolean(debug) => profile.debug = Some(*debug),
+                _ => return Err(invalid(key, "a boolean")),
+            },
+            "max-warnings" => match value {
+                config::Value::Integer(budget) => {
+                    profile.max_warnings =
+                        Some(u32::try_from(*budget).map_err(|_| invalid(key, "a count"))?);
+                }
+                _ => return Err(invalid(key, "an integer")),
+            },
+            _ => {
+                return Err(CliError::InvalidConfigValue(format!(
+                    "Unknown key: {}{}",
+                    prefix, key
+                )))
+            }
+        }
+    }
+    Ok(profile)
+}
+
+/// Finds the veshader.toml files in the directory of a shader and its parents, farthest first
+///
+/// The search stops at the project root, so shaders outside of it have none, and skips the main
+/// config file, which is already applied. All paths are canonical.
+fn directory_configs(path: &Path, root: &Path, main_config: Option<&Path>) -> Vec<PathBuf> {
+    let mut configs: Vec<PathBuf> = path
+        .ancestors()
+        .skip(1)
+        .take_while(|dir| dir.starts_with(root))
+        .map(|dir| dir.join(DEFAULT_CONFIG_FILE))
+        .filter(|config| config.is_file() && Some(config.as_path()) != main_config)
+        .collect();
+    configs.reverse();
+    configs
+}
+
+/// Applies the settings of the veshader.toml files in the directory of a shader and its parents
+///
+/// Their keys before the first table are read like a profile, and nearer files override farther
+/// ones, which in turn override the profile; flags still take precedence. Returns `None` if there
+/// is no such file.
+fn directory_settings(path: &Path, settings: &Settings) -> Result<Option<Settings>, CompilerError> {
+    let root = match &settings.project_root {
+        Some(root) => root,
+        None => return Ok(None),
+    };
+    let configs = directory_configs(
+        &fs::canonicalize(path)?,
+        root,
+        settings.config_file.as_deref(),
+    );
+    if configs.is_empty() {
+        return Ok(None);
+    }
+
+    let mut merged = Profile::default();
+    for config_path in &configs {
+        debug!("Using directory config file: {}", config_path.display());
+        let invalid = |message: String| {
+            CompilerError::InvalidDirectoryConfig(config_path.display().to_string(), message)
+        };
+        let config = Config::parse(&fs::read_to_string(config_path)?)
+            .map_err(|err| invalid(err.to_string()))?;
+        let profile = match config.table("") {
+            Some(table) => parse_profile(table, "").map_err(|err| invalid(err.to_string()))?,
+            None => continue,
+        };
+        if profile.max_warnings.is_some() {
+            // the budget counts the warnings of the whole run
+            return Err(invalid(String::from(
+                "max-warnings can only be set by a profile",
+            )));
+        }
+        merged.defines.extend(profile.defines);
+        merged.target_version = profile.target_version.or(merged.target_version);
+        merged.target_env = profile.target_env.or(merged.target_env);
+        merged.spirv_version = profile.spirv_version.or(merged.spirv_version);
+        merged.optimization = profile.optimization.or(merged.optimization);
+        merged.debug = profile.debug.or(merged.debug);
+    }
+
+    let explicit = &settings.explicit;
+    let mut directory_settings = settings.clone();
+    for define in merged.defines.iter().chain(&explicit.defines) {
+        push_define(&mut directory_settings.defines, define);
+    }
+    directory_settings.target_version = explicit
+        .target_version
+        .or(merged.target_version)
+        .unwrap_or(settings.target_version);
+    directory_settings.target_env = explicit
+        .target_env
+        .or(merged.target_env)
+        .unwrap_or(settings.target_env);
+    directory_settings.spirv_version = explicit
+        .spirv_version
+        .or(merged.spirv_version)
+        .or(settings.spirv_version);
+    directory_settings.optimization = explicit
+        .optimization
+        .or(merged.optimization)
+        .unwrap_or(settings.optimization);
+    directory_settings.debug = explicit.debug.or(merged.debug).unwrap_or(settings.debug);
+    Ok(Some(directory_settings))
+}
+
+/// Looks up a variant in the config file
+fn load_variant(name: &str, config: &Config) -> Result<Variant, CliError> {
+    let table = config
+        .table(&format!("variant.{}", name))
+        .ok_or_else(|| CliError::UnknownVariant(String::from(name)))?;
+
+    let invalid = || {
+        CliError::InvalidConfigValue(format!(
+            "Expected an array of strings: variant.{}.defines",
+            name
+        ))
+    };
+    let mut defines = Vec::new();
+    for (key, value) in table {
+        match (key.as_str(), value) {
+            ("defines", config::Value::Array(values)) => {
+                for define in values {
+                    defines.push(String::from(define.as_str().ok_or_else(invalid)?));
+                }
+            }
+            ("defines", _) => return Err(invalid()),
+            _ => {
+                return Err(CliError::InvalidConfigValue(format!(
+                    "Unknown key: variant.{}.{}",
+                    name, key
+                )))
+            }
+        }
+    }
+    Ok(Variant {
+        name: String::from(name),
+        defines,
+    })
+}
+
+/// Reads the patterns of the ignore file in the working directory, if it exists
+fn load_ignore_file() -> Result<Vec<glob::Pattern>, CliError> {
+    if !Path::new(IGNORE_FILE).is_file() {
+        return Ok(Vec::new());
+    }
+    debug!("Using ignore file: {}", IGNORE_FILE);
+    let patterns = fs::read_to_string(IGNORE_FILE)
+        .map_err(CliError::IgnoreFileRead)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(glob::Pattern::new)
+        .collect::<Result<_, _>>()?;
+    Ok(patterns)
+}
+
+/// Checks whether a path matches a pattern of the ignore file
+fn is_ignored(path: &Path, settings: &Settings) -> bool {
+    if settings.ignored.is_empty() {
+        return false;
+    }
+    // patterns are relative to the working directory, without a leading `./`
+    let path = std::env::current_dir()
+        .ok()
+        .and_then(|dir| path.strip_prefix(dir).ok())
+        .unwrap_or(path);
+    let path: PathBuf = path
+        .components()
+        .filter(|component| *component != std::path::Component::CurDir)
+        .collect();
+    settings
+        .ignored
+        .iter()
+        .any(|pattern| pattern.matches_path_with(&path, GLOB_OPTIONS))
+}
+
+/// Finds the shaders named by a JSON reference file, as globs that match exactly these files
+///
+/// Every string value with the source file extension is a reference, relative to the directory
+/// of the reference file.
+fn load_references(
+    path: &Path,
+    language: shaderc::SourceLanguage,
+) -> Result<Vec<String>, CliError> {
+    let source = fs::read_to_string(path).map_err(CliError::ReferenceFileRead)?;
+    let document = json::parse(&source).map_err(CliError::InvalidReferenceFile)?;
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut patterns = Vec::new();
+    for reference in document.strings() {
+        let is_shader = Path::new(reference)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| extension.eq_ignore_ascii_case(source_extension(language)));
+        if !is_shader {
+            continue;
+        }
+        let shader = base.join(reference);
+        if !shader.is_file() {
+            warn!(
+                "Shader {} referenced by {} does not exist.",
+                shader.display(),
+                path.display()
+            );
+            continue;
+        }
+        let pattern = glob::Pattern::escape(&shader.to_string_lossy());
+        if !patterns.contains(&pattern) {
+            patterns.push(pattern);
+        }
+    }
+    debug!(
+        "Found {} shaders referenced by {}",
+        patterns.len(),
+        path.display()
+    );
+    Ok(patterns)
+}
+
+/// File extension of shader sources in the given language
+fn source_extension(language: shaderc::SourceLanguage) -> &'static str {
+    match language {
+        shaderc::SourceLanguage::GLSL => "glsl",
+        shaderc::SourceLanguage::HLSL => "hlsl",
+    }
+}
+
+/// Expands the globs to the shader files that should be compiled
+fn collect_shaders(
+    patterns: &[String],
+    settings: &Settings,
+    log_skipped: bool,
+) -> Result<Vec<PathBuf>, CliError> {
+    let source_extension = source_extension(settings.language);
+
+    let globs = patterns
+        .iter()
+        .map(|pattern| glob::glob_with(pattern, GLOB_OPTIONS))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut paths = Vec::new();
+    for path in globs.into_iter().flatten() {
+        let path = path?;
+
+        if is_ignored(&path, settings) {
+            if log_skipped {
+                debug!(
+                    "Skipped {}, because it is listed in {}",
+                    path.display(),
+                    IGNORE_FILE
+                );
+            }
+            continue;
+        }
+
+        // check extension
+        if let Some(Some(extension)) = path.extension().map(|x| x.to_str()) {
+            if extension.to_ascii_lowercase() != source_extension && !settings.ignore_extension {
+                if log_skipped {
+                    warn!("Skipped {} because it does not have the .{} file extension. Ignore with --ignore-extension.", path.display(), source_extension);
+                }
+            } else {
+                paths.push(path);
+            }
+        } else if log_skipped {
+            warn!(
+                "Ignored file \"{}\", because no file extension was found.",
+                path.display()
+            );
+        }
+    }
+    // headers are compiled as part of the files including them
+    if settings.includes_as_dependencies {
+        let included = included_files(&paths, settings);
+        paths.retain(|path| {
+            let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.clone());
+            let header = included.contains(&canonical);
+            if header && log_skipped {
+                debug!(
+                    "Skipped {}, because other shaders include it",
+                    path.display()
+                );
+            }
+            !header
+        });
+    }
+    // globs are matched one after another, in an order that depends on the file system
+    if settings.sort {
+        paths.sort();
+        paths.dedup();
+    }
+    Ok(paths)
+}
+
+/// Finds the files included by any of the shaders, canonicalized
+///
+/// Shaders whose includes can't be resolved are left out; compiling them reports the error.
+fn included_files(paths: &[PathBuf], settings: &Settings) -> HashSet<PathBuf> {
+    let mut included = HashSet::new();
+    for path in paths {
+        if let Ok(source) = fs::read_to_string(path) {
+            let _ = flatten_includes(
+                &source,
+                &path.to_string_lossy(),
+                &include_dirs(path, settings),
+                &mut included,
+                settings,
+                0,
+            );
+        }
+    }
+    included
+}
+
+/// Recompiles the shaders whenever they are modified, until interrupted
+fn watch(
+    patterns: &[String],
+    compiler: &mut shaderc::Compiler,
+    options: &shaderc::CompileOptions,
+    settings: &Settings,
+) -> Result<(), CliError> {
+    let mut modified: HashMap<PathBuf, SystemTime> = HashMap::new();
+    let mut failing: BTreeMap<PathBuf, String> = BTreeMap::new(); // last error of each broken file
+    let mut stats = Stats::default(); // keeps the archived files of unchanged shaders
+    let mut first_run = true;
+
+    info!("Watching for changes...");
+    loop {
+        let paths = collect_shaders(patterns, settings, first_run)?;
+        first_run = false;
+
+        // forget removed files
+        modified.retain(|path, _| paths.contains(path));
+        stats.block_hashes.retain(|path, _| paths.contains(path));
+        let failing_count = failing.len();
+        failing.retain(|path, _| paths.contains(path));
+        let mut rebuilt = failing.len() != failing_count;
+
+        for path in paths {
+            let mtime = match fs::metadata(&path).and_then(|meta| meta.modified()) {
+                Ok(mtime) => mtime,
+                Err(_) => continue, // removed in the meantime
+            };
+            if modified.get(&path) == Some(&mtime) {
+                continue;
+            }
+            modified.insert(path.clone(), mtime);
+            rebuilt = true;
+
+            let options = options.clone().expect("Couldn't clone shader options.");
+            info!("Compiling shader at path: {}", path.display());
+            match parse(path.clone(), compiler, options, settings, &mut stats) {
+                Ok(()) => {
+                    failing.remove(&path);
+                }
+                Err(err) => {
+                    log_error(err.code(), &err); // handles CompilerError
+                    failing.insert(path, err.to_string());
+                }
+            }
+        }
+
+        // status after each rebuild
+        if rebuilt {
+            if let Err(err) = write_archive(settings, &stats) {
+                log_error(err.code(), &err);
+            }
+            if failing.is_empty() {
+                info!("All shaders compiled successfully.");
+            } else {
+                let names: Vec<_> = failing
+                    .keys()
+                    .map(|path| path.display().to_string())
+                    .collect();
+                warn!("Currently failing: [{}]", names.join(", "));
+            }
+        }
+
+        thread::sleep(WATCH_INTERVAL);
+    }
+}
+
+/// Lines of the compiler's messages up to the given severity; glslang prefixes notes with `NOTE:`
+fn printed_diagnostics(messages: &str, level: DiagnosticLevel) -> String {
+    messages
+        .lines()
+        .filter(|line| {
+            let severity = if line.to_ascii_lowercase().contains("note:") {
+                DiagnosticLevel::Note
+            } else {
+                DiagnosticLevel::Warning
+            };
+            severity <= level
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Files included by the current compilation: canonicalized path -> path as resolved
+type Included = Rc<RefCell<BTreeMap<PathBuf, PathBuf>>>;
+
+/// Result of each block, by index in the file
+type BlockResults = Vec<(usize, Result<(), CompilerError>)>;
+
+/// One of the shaders compiled from a block: a variant, or its debug build
+struct Build {
+    /// Part of the output file names, e.g. `debug` in foo-frag.debug.spv
+    name: Option<String>,
+    /// Unoptimized with debug info, regardless of the per-stage optimization
+    debug: bool,
+    /// Label of the block, for log lines and errors
+    label: Option<String>,
+    /// Add the entry point to the output file names, to tell apart blocks of the same stage
+    entry_in_name: bool,
+}
+
+/// Code following a TYPE instruction, compiled into one shader
+#[derive(Clone)]
+struct Block {
+    kind: shaderc::ShaderKind,
+    /// Further stages of the TYPE instruction, which the block is also compiled as
+    more_kinds: Vec<shaderc::ShaderKind>,
+    source: String,
+    /// Line in the input file of each line of `source`
+    line_mapping: Vec<usize>,
+    version: Option<String>,
+    /// Set with a LABEL instruction, e.g. `//# LABEL "shadow pass"`
+    label: Option<String>,
+    /// Set with an ENTRY instruction, instead of the entry point of the file
+    entry_point: Option<String>,
+    /// Another block of the file has the same stage
+    shares_stage: bool,
+}
+
+/// Splits `//# KEYWORD value` into the keyword and the rest of the line, which may contain spaces
+fn split_instruction(line: &str) -> Option<(&str, &str)> {
+    let instruction = line.split_once("//#")?.1.trim();
+    if instruction.is_empty() {
+        return None;
+    }
+    Some(match instruction.split_once(char::is_whitespace) {
+        Some((keyword, value)) => (keyword, value.trim()),
+        None => (instruction, ""),
+    })
+}
+
+/// An `//# IF NAME` instruction, until its ENDIF
+struct Condition {
+    /// Lines are kept, as far as this condition goes
+    active: bool,
+    /// Line of the IF instruction, for the error when it isn't closed
+    line: usize,
+    after_else: bool,
+}
+
+/// The IF instructions open at a line of a file, innermost last
+#[derive(Default)]
+struct Conditions(Vec<Condition>);
+
+impl Conditions {
+    /// Handles an IF, ELSE or ENDIF instruction at `line`; returns false for other instructions
+    fn apply(
+        &mut self,
+        (keyword, value): (&str, &str),
+        line: usize,
+        is_defined: impl Fn(&str) -> bool,
+    ) -> Result<bool, &'static str> {
+        match keyword {
+            "IF" => {
+                if value.is_empty() {
+                    return Err("IF needs the name of a define");
+                }
+                if value.contains(char::is_whitespace) {
+                    return Err("IF takes the name of a single define");
+                }
+                self.0.push(Condition {
+                    active: is_defined(value),
+                    line,
+                    after_else: false,
+                });
+            }
+            "ELSE" => {
+                let condition = self.0.last_mut().ok_or("ELSE without a matching IF")?;
+                if condition.after_else {
+                    return Err("ELSE after the ELSE of the same IF");
+                }
+                condition.active = !condition.active;
+                condition.after_else = true;
+            }
+            "ENDIF" => {
+                self.0.pop().ok_or("ENDIF without a matching IF")?;
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Whether the lines are kept: all open conditions hold
+    fn active(&self) -> bool {
+        self.0.iter().all(|condition| condition.active)
+    }
+
+    /// Line of the innermost IF without ENDIF
+    fn unclosed(&self) -> Option<usize> {
+        self.0.last().map(|condition| condition.line)
+    }
+}
+
+/// The define is passed to the tool (-D, --defines-file, the profile) or set in the front matter
+fn is_defined(name: &str, front_matter: &FrontMatter, settings: &Settings) -> bool {
+    settings
+        .defines
+        .iter()
+        .chain(&front_matter.defines)
+        .any(|define| define.split('=').next().unwrap_or_default() == name)
+}
+
+/// Parses a shader file in the custom format
+fn parse(
+    path: PathBuf,
+    compiler: &mut shaderc::Compiler,
+    mut options: shaderc::CompileOptions,
+    settings: &Settings,
+    stats: &mut Stats,
+) -> Result<(), CompilerError> {
+    // shaderc takes file names as &str
+    if path.to_str().is_none() {
+        return Err(CompilerError::NonUnicodePath(path.display().to_string()));
+    }
+
+    // settings of the veshader.toml files above the shader
+    let directory_settings = directory_settings(&path, settings)?;
+    if let Some(directory_settings) = &directory_settings {
+        options = compile_options(directory_settings).ok_or_else(|| {
+            CompilerError::Compilation(String::from("Couldn't create the shader options"))
+        })?;
+    }
+    let settings = directory_settings.as_ref().unwrap_or(settings);
+
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut curr_shader = String::new();
+    let mut shader_type: Option<shaderc::ShaderKind> = None;
+    let mut line_mapping: Vec<usize> = Vec::new();
+    let mut version: Option<String> = None;
+    let mut version_since_type = false; // VERSION was set since the last TYPE instruction
+    let mut more_kinds: Vec<shaderc::ShaderKind> = Vec::new(); // of the current TYPE instruction
+    let mut label: Option<String> = None;
+    let mut block_entry_point: Option<String> = None;
+    let mut front_matter = FrontMatter::default();
+    let mut front_matter_lines: Option<Vec<String>> = None; // inside the front matter
+    let mut conditions = Conditions::default();
+    let invalid_front_matter =
+        |message: String| CompilerError::InvalidFrontMatter(path.display().to_string(), message);
+
+    let file = File::open(&path)?;
+    // read line-by-line
+    for (idx, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|err| {
+            if err.kind() == io::ErrorKind::InvalidData {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}:{} is not valid UTF-8", path.display(), idx + 1),
+                )
+            } else {
+                err
+            }
+        })?;
+        // front matter, instead of instructions
+        if idx == 0 && line.trim() == front_matter::DELIMITER {
+            front_matter_lines = Some(Vec::new());
+            continue;
+        }
+        if let Some(lines) = &mut front_matter_lines {
+            if line.trim() != front_matter::DELIMITER {
+                lines.push(line);
+                continue;
+            }
+            front_matter = front_matter::parse(lines, 2).map_err(invalid_front_matter)?;
+            front_matter_lines = None;
+            if let Some(stage) = &front_matter.stage {
+                let kind = parse_shader_kind(&stage.to_ascii_uppercase())
+                    .ok_or_else(|| CompilerError::UnknownShaderType(stage.clone()))?;
+                shader_type = Some(kind);
+            }
+            if let Some(front_matter_version) = &front_matter.version {
+                version = Some(parse_version(front_matter_version).map_err(invalid_front_matter)?);
+            }
+            continue;
+        }
+        let invalid_instruction = |message: &str| {
+            CompilerError::InvalidInstruction(
+                format!("{}:{}", path.display(), idx + 1),
+                String::from(message),
+            )
+        };
+        // conditional compilation, before any other instruction
+        if let Some(instruction) = split_instruction(&line) {
+            let is_defined = |name: &str| is_defined(name, &front_matter, settings);
+            if conditions
+                .apply(instruction, idx + 1, is_defined)
+                .map_err(invalid_instruction)?
+            {
+                continue;
+            }
+        }
+        // inside an IF whose define isn't set, or its ELSE when it is
+        if !conditions.active() {
+            continue;
+        }
+        // custom format intsruction
+        if line.contains("//#") {
+            // parse custom instructions
+            if let Some((instruction, value)) = split_instruction(&line) {
+                // handle TYPE instruction
+                if instruction.contains("TYPE") {
+                    // parse instruction arguments
+                    let mut tokens = value.split_whitespace();
+                    if let Some(token) = tokens.next() {
+                        let new_kind = parse_shader_kind(token)
+                            .ok_or_else(|| CompilerError::UnknownShaderType(String::from(token)))?;
+                        // further stages compile the same block, e.g. //# TYPE VERTEX FRAGMENT
+                        let new_more_kinds = tokens
+                            .map(|token| {
+                                parse_shader_kind(token).ok_or_else(|| {
+                                    CompilerError::UnknownShaderType(String::from(token))
+                                })
+                            })
+                            .collect::<Result<Vec<_>, _>>()?;
+                        if let Some(kind) = shader_type {
+                            blocks.push(Block {
+                                kind,
+                                more_kinds: std::mem::take(&mut more_kinds),
+                                source: curr_shader,
+                                line_mapping,
+                                version: version.clone(),
+                                label: label.take(),
+                                entry_point: block_entry_point.take(),
+                                shares_stage: false,
+                            });
+
+                            curr_shader = String::new();
+                            line_mapping = Vec::new();
+                        }
+                        shader_type = Some(new_kind);
+                        more_kinds = new_more_kinds;
+                        version_since_type = false;
+                    }
+                } else if instruction.contains("VERSION") && !value.is_empty() {
+                    if version_since_type {
+                        if let Some(old_version) = &version {
+                            warn!(
+                                "{}:{}: VERSION was set again without a TYPE in between; discarding the earlier version {}",
+                                path.display(),
+                                idx + 1,
+                                old_version
+                            );
+                        }
+                    }
+                    version = Some(
+                        parse_version(value.split_whitespace().next().unwrap_or_default())
+                            .map_err(|message| invalid_instruction(&message))?,
+                    );
+                    version_since_type = true;
+                } else if instruction.contains("LABEL") {
+                    let value = value.trim_matches('"');
+                    if !value.is_empty() {
+                        label = Some(String::from(value));
+                    }
+                } else if instruction.contains("ENTRY") && !value.is_empty() {
+                    block_entry_point = Some(String::from(value));
+                }
+            }
+        } else if curr_shader.is_empty() {
+            curr_shader = line;
+            line_mapping.push(idx + 1);
+        } else {
+            // ignore empty lines and comments
+            if !line.is_empty() && !line.starts_with("//") {
+                curr_shader = format!("{}\n{}", &curr_shader, &line);
+                line_mapping.push(idx + 1);
+            }
+        }
+    }
+
+    if front_matter_lines.is_some() {
+        return Err(invalid_front_matter(format!(
+            "missing the closing {}",
+            front_matter::DELIMITER
+        )));
+    }
+    if let Some(line) = conditions.unclosed() {
+        return Err(CompilerError::InvalidInstruction(
+            format!("{}:{}", path.display(), line),
+            String::from("IF without a matching ENDIF"),
+        ));
+    }
+
+    // stage of a file without TYPE instruction
+    if shader_type.is_none() && !settings.strict {
+        if let Some((kind, source)) = infer_stage(&path, settings) {
+            debug!(
+                "No TYPE instruction in {}, took {:?} from {}",
+                path.display(),
+                kind,
+                source
+            );
+            shader_type = Some(kind);
+        }
+    }
+
+    if shader_type.is_none() {
+        if settings.strict {
+            return Err(CompilerError::MissingInstruction("TYPE"));
+        }
+        warn!(
+            "No shader compiled from {}, because it contains no //# TYPE instruction.",
+            path.display()
+        );
+    }
+
+    // last shader
+    if let Some(kind) = shader_type {
+        blocks.push(Block {
+            kind,
+            more_kinds,
+            source: curr_shader,
+            line_mapping,
+            version,
+            label,
+            entry_point: block_entry_point,
+            shares_stage: false,
+        });
+    }
+    // blocks with several stages are compiled as each of them
+    let mut blocks: Vec<Block> = blocks
+        .into_iter()
+        .flat_map(|block| {
+            let copies: Vec<Block> = block
+                .more_kinds
+                .iter()
+                .map(|&kind| Block {
+                    kind,
+                    more_kinds: Vec::new(),
+                    ..block.clone()
+                })
+                .collect();
+            std::iter::once(block).chain(copies)
+        })
+        .collect();
+    for idx in 0..blocks.len() {
+        let kind = blocks[idx].kind;
+        blocks[idx].shares_stage = blocks.iter().filter(|block| block.kind == kind).count() > 1;
+    }
+
+    if settings.emit_compile_db {
+        record_compile_commands(&path, &blocks, &front_matter, settings, stats)?;
+    }
+
+    // skip the blocks that didn't change since they were last compiled successfully; linking
+    // needs all of them
+    let previous = stats.block_hashes.remove(&path).unwrap_or_default();
+    let mut up_to_date = HashSet::new();
+    let mut changed = Vec::new();
+    for block in blocks {
+        let hash = block_hash(&block, &front_matter);
+        if previous.contains(&hash) && !settings.link && !settings.force {
+            debug!(
+                "{:?} block of {} is unchanged, skipping",
+                block.kind,
+                path.display()
+            );
+            up_to_date.insert(hash);
+        } else {
+            changed.push((hash, block));
+        }
+    }
+
+    let compiled_before = stats.compiled.len();
+    if settings.threads_per_file > 1 && changed.len() > 1 {
+        let (hashes, blocks): (Vec<_>, Vec<_>) = changed.into_iter().unzip();
+        let result = compile_in_parallel(&path, blocks, &front_matter, settings, stats);
+        if result.is_ok() {
+            up_to_date.extend(hashes);
+        }
+        stats.block_hashes.insert(path.clone(), up_to_date);
+        result?;
+        return link_stages(&path, compiled_before, settings, stats);
+    }
+
+    let (entry_point, included) = file_options(&path, &mut options, &front_matter, settings)?;
+    let mut result = Ok(());
+    for (hash, block) in changed {
+        result = compile_variants(
+            compiler,
+            &block,
+            &path,
+            &options,
+            &entry_point,
+            &included,
+            settings,
+            stats,
+        );
+        if result.is_err() {
+            break;
+        }
+        up_to_date.insert(hash);
+    }
+    stats.block_hashes.insert(path.clone(), up_to_date);
+    result?;
+    link_stages(&path, compiled_before, settings, stats)
+}
+
+/// Links the binaries compiled from a file, from `stats.compiled[compiled_before..]`, into one
+/// module per variant with spirv-link, e.g. foo.spv from foo-vert.spv and foo-frag.spv
+///
+/// Files with several blocks of the same stage are skipped, since spirv-link rejects modules with
+/// the same entry point. The linked module is checked against --max-size and post-processed like
+/// the other binaries.
+fn link_stages(
+    path: &Path,
+    compiled_before: usize,
+    settings: &Settings,
+    stats: &mut Stats,
+) -> Result<(), CompilerError> {
+    let output_dir = match (&settings.output, settings.link) {
+        (Some(Output::Directory(output_dir)), true) => output_dir,
+        _ => return Ok(()),
+    };
+    let mut stages: Vec<&str> = Vec::new();
+    let mut compiled = HashSet::new();
+    let mut shares_stage = false;
+    let mut variants: BTreeMap<Option<String>, Vec<String>> = BTreeMap::new();
+    for shader in &stats.compiled[compiled_before..] {
+        if !stages.contains(&shader.stage.as_str()) {
+            stages.push(&shader.stage);
+        }
+        shares_stage |= !compiled.insert((&shader.variant, &shader.stage));
+        variants.entry(shader.variant.clone()).or_default().extend(
+            shader
+                .outputs
+                .iter()
+                .filter(|name| name.ends_with(".spv"))
+                .cloned(),
+        );
+    }
+    if shares_stage {
+        let message =
+            String::from("not linking its stages, because several blocks have the same stage");
+        warn!("{}: {}", path.display(), message);
+        stats.warnings += 1;
+        if let Some(shader) = stats.compiled.last_mut() {
+            shader.warnings.push(message);
+        }
+        return Ok(());
+    }
+    let stages = stages.join(",");
+
+    let stem = path.file_stem().expect("Invalid path").to_string_lossy();
+    for (variant, inputs) in variants {
+        if inputs.len() < 2 {
+            continue;
+        }
+        let name = match &variant {
+            Some(variant) => format!("{}.{}.spv", stem, variant),
+            None => format!("{}.spv", stem),
+        };
+        let linked = output_dir.join(&name);
+        debug!("Linking {} into {}", inputs.join(", "), name);
+        let result = Process::new("spirv-link")
+            .args(inputs.iter().map(|input| output_dir.join(input)))
+            .arg("-o")
+            .arg(&linked)
+            .output()
+            .map_err(|err| format!("couldn't run spirv-link: {}", err))
+            .and_then(|output| {
+                if output.status.success() {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "spirv-link exited with {}: {}",
+                        output.status,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    ))
+                }
+            });
+        result.map_err(|message| CompilerError::Link(path.display().to_string(), message))?;
+        info!("Linked the stages of {} into {}", path.display(), name);
+
+        if let Some(max_size) = settings.max_size {
+            let size = fs::metadata(&linked)?.len() as usize;
+            if size > max_size {
+                fs::remove_file(&linked)?;
+                return Err(CompilerError::TooLarge(
+                    path.display().to_string(),
+                    String::from("linked"),
+                    size,
+                    max_size,
+                ));
+            }
+        }
+        if let Some(command) = &settings.post_process {
+            post_process(command, &linked, path, &stages, "").map_err(|message| {
+                CompilerError::PostProcess(path.display().to_string(), message)
+            })?;
+        }
+        stats.written.push(name);
+    }
+    Ok(())
+}
+
+/// Adds the glslc arguments of each stage of a file to the compile database
+///
+/// They have the macro definitions and include directories of the file, but not those of the
+/// variants; the custom format with several stages in one file is only understood by ve_shader.
+fn record_compile_commands(
+    path: &Path,
+    blocks: &[Block],
+    front_matter: &FrontMatter,
+    settings: &Settings,
+    stats: &mut Stats,
+) -> Result<(), CompilerError> {
+    let (sidecar_entry_point, sidecar_defines) = load_sidecar(path)?;
+    let file_entry_point = front_matter
+        .entry_point
+        .clone()
+        .unwrap_or(sidecar_entry_point);
+    let target_env = match settings.target_env {
+        shaderc::TargetEnv::Vulkan => {
+            table_name(TARGET_VERSIONS, settings.target_version).replace('_', ".")
+        }
+        env => String::from(table_name(TARGET_ENVS, env)),
+    };
+
+    let mut recorded = HashSet::new();
+    for block in blocks {
+        let entry_point = block.entry_point.as_ref().unwrap_or(&file_entry_point);
+        let stage = table_name(SHADER_KINDS, block.kind);
+        if !recorded.insert((stage, entry_point.clone())) {
+            continue;
+        }
+        let mut arguments = vec![
+            String::from("glslc"),
+            format!("-fshader-stage={}", stage.to_ascii_lowercase()),
+            format!("--target-env={}", target_env),
+            format!("-fentry-point={}", entry_point),
+            String::from("-x"),
+            String::from(source_extension(settings.language)),
+        ];
+        if let Some(spirv_version) = settings.spirv_version {
+            arguments.push(format!(
+                "--target-spv=spv{}",
+                table_name(SPIRV_VERSIONS, spirv_version)
+            ));
+        }
+        if let Some(version) = settings.forced_version {
+            arguments.push(format!("-std={}", version));
+        }
+        arguments.push(String::from(match settings.optimization {
+            shaderc::OptimizationLevel::Zero => "-O0",
+            shaderc::OptimizationLevel::Size => "-Os",
+            shaderc::OptimizationLevel::Performance => "-O",
+        }));
+        if settings.debug {
+            arguments.push(String::from("-g"));
+        }
+        let stage_define = format!("VE_STAGE_{}", stage);
+        let defines = settings
+            .defines
+            .iter()
+            .chain(&sidecar_defines)
+            .chain(&front_matter.defines)
+            .chain(std::iter::once(&stage_define));
+        arguments.extend(defines.map(|define| format!("-D{}", define)));
+        arguments.extend(
+            include_dirs(path, settings)
+                .iter()
+                .map(|dir| format!("-I{}", dir.display())),
+        );
+        arguments.push(String::from("-c"));
+        arguments.push(path.display().to_string());
+        stats.compile_commands.push(CompileCommand {
+            path: path.to_path_buf(),
+            arguments,
+        });
+    }
+    Ok(())
+}
+
+/// Hashes everything of a block that affects its outputs in the file: its code, stages, version,
+/// entry point and name, and the front matter
+fn block_hash(block: &Block, front_matter: &FrontMatter) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    block.source.hash(&mut hasher);
+    block.line_mapping.hash(&mut hasher);
+    format!("{:?} {:?}", block.kind, block.more_kinds).hash(&mut hasher);
+    block.version.hash(&mut hasher);
+    // the entry point is compiled in, and the label and a shared stage change the output name
+    block.entry_point.hash(&mut hasher);
+    block.label.hash(&mut hasher);
+    block.shares_stage.hash(&mut hasher);
+    front_matter.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Finds the stage of a file without TYPE instruction, and names where it came from
+///
+/// In order of precedence: --stage, the file extension (e.g. foo.comp), the extension before it
+/// (e.g. foo.comp.glsl), the directory with --stage-from-dir (e.g. vertex/foo.glsl) and
+/// --default-stage.
+fn infer_stage(path: &Path, settings: &Settings) -> Option<(shaderc::ShaderKind, &'static str)> {
+    if let Some(kind) = settings.stage {
+        return Some((kind, "--stage"));
+    }
+    let stage_of = |path: &Path| {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(|extension| match extension.to_ascii_lowercase().as_str() {
+                "vert" => Some(shaderc::ShaderKind::Vertex),
+                "frag" => Some(shaderc::ShaderKind::Fragment),
+                "geom" => Some(shaderc::ShaderKind::Geometry),
+                "comp" => Some(shaderc::ShaderKind::Compute),
+                _ => None,
+            })
+    };
+    if let Some(kind) = stage_of(path) {
+        return Some((kind, "the file extension"));
+    }
+    // glslangValidator style, e.g. foo.vert.glsl
+    if let Some(kind) = path.file_stem().and_then(|stem| stage_of(Path::new(stem))) {
+        return Some((kind, "the secondary file extension"));
+    }
+    if settings.stage_from_dir {
+        let from_dir = path
+            .parent()
+            .and_then(Path::file_name)
+            .and_then(|dir| dir.to_str())
+            .and_then(|dir| parse_shader_kind(&dir.to_ascii_uppercase()));
+        if let Some(kind) = from_dir {
+            return Some((kind, "the directory"));
+        }
+    }
+    settings.default_stage.map(|kind| (kind, "--default-stage"))
+}
+
+/// Checks the argument of a VERSION instruction, which must be a bare number like 450
+fn parse_version(token: &str) -> Result<String, String> {
+    if token.is_empty() || !token.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!(
+            "Invalid VERSION instruction, expected a GLSL version number like 450: {}",
+            token
+        ));
+    }
+    Ok(String::from(token))
+}
+
+/// Applies the settings of a file to its compile options: the sidecar file, the front matter and
+/// the include callback, which gets the included files from the file system
+///
+/// Returns the entry point and the files included by the current compilation, for --include-once.
+fn file_options(
+    path: &Path,
+    options: &mut shaderc::CompileOptions,
+    front_matter: &FrontMatter,
+    settings: &Settings,
+) -> Result<(String, Included), CompilerError> {
+    // settings of the sidecar file, then of the front matter
+    let (mut entry_point, sidecar_defines) = load_sidecar(path)?;
+    for define in &sidecar_defines {
+        add_define(options, define);
+    }
+    if let Some(name) = &front_matter.entry_point {
+        entry_point = name.clone();
+    }
+    for define in &front_matter.defines {
+        add_define(options, define);
+    }
+
+    let included: Included = Rc::default();
+    let included_by_callback = Rc::clone(&included);
+    let include_once = settings.include_once;
+    let resolver = file_resolver(path, settings);
+
+    options.set_include_callback(move |name, ty, src, _depth| {
+        let (path, content) = resolver(name, ty, src)?;
+        let path_str = path.to_str().ok_or("Non-unicode path")?.to_owned();
+
+        // track dependencies, and emulate `#pragma once`
+        let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+        let first = included_by_callback
+            .borrow_mut()
+            .insert(canonical, path.clone())
+            .is_none();
+        if include_once && !first {
+            debug!("  already included, skipping");
+            return Ok(shaderc::ResolvedInclude {
+                resolved_name: path_str,
+                content: String::new(),
+            });
+        }
+
+        Ok(shaderc::ResolvedInclude {
+            resolved_name: path_str,
+            content,
+        })
+    });
+    Ok((entry_point, included))
+}
+
+/// Resolves the includes of a shader in the file system, following the include settings
+///
+/// The resolver gets the name in the `#include`, its type and the including file, and returns the
+/// path and content of the included file.
+fn file_resolver(
+    path: &Path,
+    settings: &Settings,
+) -> impl Fn(&str, shaderc::IncludeType, &str) -> Result<(PathBuf, String), String> {
+    let include_dirs = include_dirs(path, settings);
+    let include_mode = settings.include_mode;
+    let include_root = settings.include_root.clone();
+    move |name, ty, src| {
+        let path = resolve_include(name, ty, src, &include_dirs, include_mode, &include_root)?;
+        let content = fs::read_to_string(&path).map_err(|x| x.to_string())?;
+        Ok((path, content))
+    }
+}
+
+/// Compiles the blocks of a file on up to --threads-per-file threads
+///
+/// shaderc's compiler and options aren't `Send`, so every thread creates its own. The first error
+/// in file order is returned, any others are logged.
+fn compile_in_parallel(
+    path: &Path,
+    blocks: Vec<Block>,
+    front_matter: &FrontMatter,
+    settings: &Settings,
+    stats: &mut Stats,
+) -> Result<(), CompilerError> {
+    let threads = settings.threads_per_file.min(blocks.len());
+    let mut queues: Vec<Vec<(usize, Block)>> = (0..threads).map(|_| Vec::new()).collect();
+    for (idx, block) in blocks.into_iter().enumerate() {
+        queues[idx % threads].push((idx, block));
+    }
+
+    let workers: Vec<_> = thread::scope(|scope| {
+        let handles: Vec<_> = queues
+            .into_iter()
+            .map(|queue| scope.spawn(move || compile_queue(path, queue, front_matter, settings)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Compiling a shader panicked"))
+            .collect()
+    });
+
+    let mut results = Vec::new();
+    for (idx, worker) in workers.into_iter().enumerate() {
+        let (worker_results, mut worker_stats) = worker?;
+        for span in &mut worker_stats.spans {
+            span.thread = idx + 1;
+        }
+        stats.merge(worker_stats);
+        results.extend(worker_results);
+    }
+    results.sort_by_key(|(idx, _)| *idx);
+
+    let mut errors = results.into_iter().filter_map(|(_, result)| result.err());
+    match errors.next() {
+        Some(first) => {
+            for err in errors {
+                log_error(err.code(), &err); // handles CompilerError
+            }
+            Err(first)
+        }
+        None => Ok(()),
+    }
+}
+
+/// Compiles blocks of a file with a compiler of its own; runs on a worker thread
+fn compile_queue(
+    path: &Path,
+    queue: Vec<(usize, Block)>,
+    front_matter: &FrontMatter,
+    settings: &Settings,
+) -> Result<(BlockResults, Stats), CompilerError> {
+    let worker_error = || CompilerError::Compilation(String::from("Couldn't create a compiler"));
+    let mut compiler = shaderc::Compiler::new().ok_or_else(worker_error)?;
+    let mut options = compile_options(settings).ok_or_else(worker_error)?;
+    let (entry_point, included) = file_options(path, &mut options, front_matter, settings)?;
+
+    let mut stats = Stats::default();
+    let results = queue
+        .into_iter()
+        .map(|(idx, block)| {
+            let result = compile_variants(
+                &mut compiler,
+                &block,
+                path,
+                &options,
+                &entry_point,
+                &included,
+                settings,
+                &mut stats,
+            );
+            (idx, result)
+        })
+        .collect();
+    Ok((results, stats))
+}
+
+/// Search paths for standard includes of a shader
+fn include_dirs(path: &Path, settings: &Settings) -> Vec<PathBuf> {
+    let mut include_dirs = settings.include_dirs.clone();
+    if settings.base_include_dir && !settings.glslc_includes {
+        if let Some(parent) = path.parent() {
+            include_dirs.push(parent.to_path_buf());
+        }
+    }
+    include_dirs
+}
+
+/// Finds the file an `#include` refers to
+fn resolve_include(
+    name: &str,
+    ty: shaderc::IncludeType,
+    src: &str,
+    include_dirs: &[PathBuf],
+    mode: IncludeMode,
+    root: &Path,
+) -> Result<PathBuf, String> {
+    if mode == IncludeMode::Gcc {
+        return resolve_include_like_glslc(name, ty, src, include_dirs);
+    }
+    debug!("Resolving include {:?} ({:?}) from {}", name, ty, src);
+    let path = match (ty, mode) {
+        // relative to the including file
+        (shaderc::IncludeType::Relative, IncludeMode::QuoteRelative) => {
+            Path::new(src).parent().unwrap().join(name)
+        }
+        // relative to the project root
+        (shaderc::IncludeType::Relative, IncludeMode::Root) => root.join(name),
+        // first match in the include directories
+        _ => include_dirs
+            .iter()
+            .map(|dir| dir.join(name))
+            .find(|path| {
+                debug!("  trying {}", path.display());
+                path.is_file()
+            })
+            .ok_or_else(|| format!("Include not found in include directories: {}", name))?,
+    };
+    debug!("  resolved to {}", path.display());
+    Ok(path)
+}
+
+/// Finds the file an `#include` refers to, in the order of glslc's file includer and gcc
+///
+/// `"..."` is searched next to the including file, then in the include directories; `<...>` only
+/// in the include directories.
+fn resolve_include_like_glslc(
+    name: &str,
+    ty: shaderc::IncludeType,
+    src: &str,
+    include_dirs: &[PathBuf],
+) -> Result<PathBuf, String> {
+    debug!(
+        "Resolving include {:?} ({:?}) from {} like glslc",
+        name, ty, src
+    );
+    let next_to_src = match ty {
+        shaderc::IncludeType::Relative => Path::new(src).parent().map(|dir| dir.join(name)),
+        shaderc::IncludeType::Standard => None,
+    };
+    next_to_src
+        .into_iter()
+        .chain(include_dirs.iter().map(|dir| dir.join(name)))
+        .find(|path| {
+            debug!("  trying {}", path.display());
+            path.is_file()
+        })
+        .ok_or_else(|| format!("Cannot find or open include file: {}", name))
+}
+
+/// Maximum nesting of includes when flattening, to stop at include cycles
+const MAX_INCLUDE_DEPTH: usize = 32;
+
+/// Matches `#include <name>` (name in group 2) and `#include "name"` (group 3)
+fn include_regex() -> regex::Regex {
+    regex::Regex::new(r#"^\s*#\s*include\s*(<([^>]*)>|"([^"]*)")"#)
+        .expect("Failed include flattening: invalid regex")
+}
+
+/// Prints the files a shader includes, transitively, indented by their depth
+fn print_include_tree(path: &Path, settings: &Settings) {
+    println!("{}", path.display());
+    let mut listed = HashSet::new();
+    include_tree(
+        &path.to_string_lossy(),
+        &include_dirs(path, settings),
+        &mut listed,
+        settings,
+        1,
+    );
+}
+
+/// Prints the includes of the file `src` at `depth`, and those of each newly listed include below it
+fn include_tree(
+    src: &str,
+    include_dirs: &[PathBuf],
+    listed: &mut HashSet<PathBuf>,
+    settings: &Settings,
+    depth: usize,
+) {
+    if depth > MAX_INCLUDE_DEPTH {
+        warn!("Includes nested too deeply in {}", src);
+        return;
+    }
+    let source = match fs::read_to_string(src) {
+        Ok(source) => source,
+        Err(err) => {
+            warn!("Couldn't read {}: {}", src, err);
+            return;
+        }
+    };
+    let reg = include_regex();
+    let indent = "  ".repeat(depth);
+    for captures in source.lines().filter_map(|line| reg.captures(line)) {
+        let (name, ty) = match captures.get(2) {
+            Some(name) => (name.as_str(), shaderc::IncludeType::Standard),
+            None => (&captures[3], shaderc::IncludeType::Relative),
+        };
+        let path = match resolve_include(
+            name,
+            ty,
+            src,
+            include_dirs,
+            settings.include_mode,
+            &settings.include_root,
+        ) {
+            Ok(path) => path,
+            Err(err) => {
+                println!("{}{} (not found: {})", indent, name, err);
+                continue;
+            }
+        };
+        // each file is expanded once, later includes of it only refer back
+        let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+        if !listed.insert(canonical) {
+            println!("{}{} (listed above)", indent, path.display());
+            continue;
+        }
+        println!("{}{}", indent, path.display());
+        include_tree(
+            &path.to_string_lossy(),
+            include_dirs,
+            listed,
+            settings,
+            depth + 1,
+        );
+    }
+}
+
+/// Replaces `#include` lines with the contents of the included files, recursively
+///
+/// Unlike preprocessing, macros are left as they are.
+fn flatten_includes(
+    source: &str,
+    src: &str,
+    include_dirs: &[PathBuf],
+    included: &mut HashSet<PathBuf>,
+    settings: &Settings,
+    depth: usize,
+) -> Result<String, String> {
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(format!("Includes nested too deeply in {}", src));
+    }
+    let reg = include_regex();
+
+    let mut flattened = String::new();
+    for line in source.lines() {
+        let captures = match reg.captures(line) {
+            Some(captures) => captures,
+            None => {
+                flattened.push_str(line);
+                flattened.push('\n');
+                continue;
+            }
+        };
+        let (name, ty) = match captures.get(2) {
+            Some(name) => (name.as_str(), shaderc::IncludeType::Standard),
+            None => (&captures[3], shaderc::IncludeType::Relative),
+        };
+        let path = resolve_include(
+            name,
+            ty,
+            src,
+            include_dirs,
+            settings.include_mode,
+            &settings.include_root,
+        )?;
+
+        let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+        if !included.insert(canonical) && settings.include_once {
+            continue;
+        }
+        let content = fs::read_to_string(&path).map_err(|err| err.to_string())?;
+        let path_str = path.to_string_lossy();
+        flattened.push_str(&format!("// begin include {}\n", name));
+        flattened.push_str(&flatten_includes(
+            &content,
+            &path_str,
+            include_dirs,
+            included,
+            settings,
+            depth + 1,
+        )?);
+        flattened.push_str(&format!("// end include {}\n", name));
+    }
+    Ok(flattened)
+}
+
+/// Reads the settings of the sidecar file `<file>.toml` next to a shader, if it exists
+///
+/// Returns the entry point and the macro definitions.
+fn load_sidecar(path: &Path) -> Result<(String, Vec<String>), CompilerError> {
+    let mut entry_point = String::from("main");
+    let mut sidecar_defines = Vec::new();
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".toml");
+    let sidecar = PathBuf::from(sidecar);
+    if !sidecar.is_file() {
+        return Ok((entry_point, sidecar_defines));
+    }
+    debug!("Using sidecar file: {}", sidecar.display());
+
+    let invalid =
+        |message: String| CompilerError::InvalidSidecar(sidecar.display().to_string(), message);
+    let source = fs::read_to_string(&sidecar).map_err(|err| invalid(err.to_string()))?;
+    let config = Config::parse(&source).map_err(|err| invalid(err.to_string()))?;
+    for (key, value) in config.table("").into_iter().flatten() {
+        match (key.as_str(), value) {
+            ("entry-point", config::Value::String(name)) => entry_point = name.clone(),
+            ("defines", config::Value::Array(defines)) => {
+                for define in defines {
+                    let define = define
+                        .as_str()
+                        .ok_or_else(|| invalid(String::from("Expected strings: defines")))?;
+                    sidecar_defines.push(String::from(define));
+                }
+            }
+            ("entry-point", _) => {
+                return Err(invalid(String::from("Expected a string: entry-point")))
+            }
+            ("defines", _) => return Err(invalid(String::from("Expected an array: defines"))),
+            _ => return Err(invalid(format!("Unknown key: {}", key))),
+        }
+    }
+    Ok((entry_point, sidecar_defines))
+}
+
+/// Compiles a single shader
+#[allow(clippy::too_many_arguments)]
+fn compile_variants(
+    compiler: &mut shaderc::Compiler,
+    block: &Block,
+    path: &Path,
+    options: &shaderc::CompileOptions,
+    entry_point: &str,
+    included: &RefCell<BTreeMap<PathBuf, PathBuf>>,
+    settings: &Settings,
+    stats: &mut Stats,
+) -> Result<(), CompilerError> {
+    // an ENTRY instruction takes precedence over the entry point of the file
+    let entry_point = block.entry_point.as_deref().unwrap_or(entry_point);
+    // errors of a labeled block name the label
+    let labeled = |err: CompilerError| match &block.label {
+        Some(label) => CompilerError::Labeled(label.clone(), Box::new(err)),
+        None => err,
+    };
+    // name of a build in the trace
+    let span_name = |build: &Build| {
+        let stage = stage_extension(block.kind, settings);
+        match &build.name {
+            Some(variant) => format!("{} ({}, {})", path.display(), stage, variant),
+            None => format!("{} ({})", path.display(), stage),
+        }
+    };
+
+    // the variants of the run, or just the shader itself
+    let variants: Vec<(Option<&str>, &[String])> = if settings.variants.is_empty() {
+        vec![(None, &[])]
+    } else {
+        settings
+            .variants
+            .iter()
+            .map(|variant| (Some(variant.name.as_str()), variant.defines.as_slice()))
+            .collect()
+    };
+
+    for (name, defines) in variants {
+        if let Some(name) = name {
+            debug!("Compiling variant {} of {}", name, path.display());
+        }
+        // defines of the variant replace those of the run with the same name
+        let mut variant_options = options.clone().expect("Couldn't clone shader options.");
+        // the stage, for blocks compiled as several stages
+        add_define(
+            &mut variant_options,
+            &format!("VE_STAGE_{}", table_name(SHADER_KINDS, block.kind)),
+        );
+        for define in defines {
+            add_define(&mut variant_options, define);
+        }
+        let build = Build {
+            name: name.map(String::from),
+            debug: false,
+            label: block.label.clone(),
+            entry_in_name: block.shares_stage,
+        };
+        let started = Instant::now();
+        let result = compile_shader(
+            compiler,
+            &block.source,
+            path,
+            &variant_options,
+            block.kind,
+            block.line_mapping.clone(),
+            &block.version,
+            entry_point,
+            included,
+            &build,
+            settings,
+            stats,
+        );
+        record_span(settings, stats, span_name(&build), "stage", started);
+        result.map_err(labeled)?;
+
+        // unoptimized with debug info, next to the optimized one
+        if settings.emit_debug_variant {
+            variant_options.set_optimization_level(shaderc::OptimizationLevel::Zero);
+            variant_options.set_generate_debug_info();
+            let build = Build {
+                name: Some(match name {
+                    Some(name) => format!("{}.debug", name),
+                    None => String::from("debug"),
+                }),
+                debug: true,
+                label: block.label.clone(),
+                entry_in_name: block.shares_stage,
+            };
+            let started = Instant::now();
+            let result = compile_shader(
+                compiler,
+                &block.source,
+                path,
+                &variant_options,
+                block.kind,
+                block.line_mapping.clone(),
+                &block.version,
+                entry_point,
+                included,
+                &build,
+                settings,
+                stats,
+            );
+            record_span(settings, stats, span_name(&build), "stage", started);
+            result.map_err(labeled)?;
+        }
+    }
+    Ok(())
+}
+
+/// Compiles one stage of a file, as the given build
+#[allow(clippy::too_many_arguments)]
+fn compile_shader(
+    compiler: &mut shaderc::Compiler,
+    curr_shader: &str,
+    path: &Path,
+    options: &shaderc::CompileOptions,
+    kind: shaderc::ShaderKind,
+    line_mapping: Vec<usize>,
+    version: &Option<String>,
+    entry_point: &str,
+    included: &RefCell<BTreeMap<PathBuf, PathBuf>>,
+    build: &Build,
+    settings: &Settings,
+    stats: &mut Stats,
+) -> Result<(), CompilerError> {
+    let mut added_statements = 0; // count added statements, to correct line numbers in errors
+    let mut added_lines = AddedLines::default();
+
+    // the source manages its own version
+    let version = if settings.auto_version {
+        version
+    } else {
+        if version.is_some() {
+            debug!(
+                "Ignoring the VERSION instruction of {}, because of --no-auto-version",
+                path.display()
+            );
+        }
+        &None
+    };
+
+    if version.is_none() && settings.strict && settings.auto_version {
+        return Err(CompilerError::MissingInstruction("VERSION"));
+    }
+
+    // infer the version from the target, if neither instructed nor in the source
+    let inferred_version;
+    let version = if version.is_none()
+        && settings.auto_version
+        && settings.language == shaderc::SourceLanguage::GLSL
+        && !curr_shader
+            .lines()
+            .any(|line| line.trim_start().starts_with("#version"))
+    {
+        let default_version = settings.target_version.default_glsl_version();
+        debug!(
+            "No version given for {}, inferred {} from the target version",
+            path.display(),
+            default_version
+        );
+        inferred_version = Some(String::from(default_version));
+        &inferred_version
+    } else {
+        version
+    };
+
+    // fold specialization constants
+    let specialized;
+    let curr_shader = if settings.spec_constants.is_empty() {
+        curr_shader
+    } else {
+        specialized = specialize(
+            curr_shader,
+            &settings.spec_constants,
+            &mut stats.specialized,
+        );
+        &specialized
+    };
+
+    // add prelude to curr_shader
+    let curr_shader: String = if let Some((prelude_path, prelude)) = &settings.prelude {
+        let prelude = prelude.trim_end_matches('\n');
+        let lines = prelude.split('\n').count();
+        added_statements += lines;
+        added_lines.prelude = Some((prelude_path.as_path(), lines));
+        format!("{}\n{}", prelude, curr_shader)
+    } else {
+        String::from(curr_shader)
+    };
+
+    // add version to curr_shader
+    let curr_shader: String = if let Some(version) = version {
+        added_statements += 1;
+        added_lines.version = true;
+        format!("#version {}\n{}", version, curr_shader)
+    } else {
+        curr_shader
+    };
+
+    debug!("Compiling:\n{}", &curr_shader);
+
+    // per-stage optimization level; debug builds stay unoptimized
+    let mut level = if build.debug {
+        shaderc::OptimizationLevel::Zero
+    } else {
+        settings.optimization
+    };
+    let stage_options;
+    let options = match settings
+        .stage_optimization
+        .iter()
+        .find(|(stage, _)| *stage == kind && !build.debug)
+    {
+        Some(&(_, stage_level)) => {
+            let mut o = options.clone().expect("Couldn't clone shader options.");
+            o.set_optimization_level(stage_level);
+            level = stage_level;
+            stage_options = o;
+            &stage_options
+        }
+        None => options,
+    };
+
+    // output file names
+    let output_folder = path
+        .file_stem()
+        .expect("Invalid path")
+        .to_str()
+        .ok_or_else(|| CompilerError::NonUnicodePath(path.display().to_string()))?;
+    let output_extension = stage_extension(kind, settings);
+    // disambiguate entry points of the same stage
+    let output_extension = if !build.entry_in_name || entry_point == "main" {
+        output_extension
+    } else {
+        settings.naming.join(&output_extension, entry_point)
+    };
+    // variants go between the stage and the file extension: foo-frag.debug.spv
+    let output_name = |extension: &str| {
+        let extension = match &build.name {
+            Some(variant) => format!("{}.{}", variant, extension),
+            None => String::from(extension),
+        };
+        settings
+            .naming
+            .file_name(output_folder, &output_extension, &extension)
+    };
+    let flat_extension = format!("flat.{}", source_extension(settings.language));
+    let output_names: Vec<String> = [
+        (settings.emit_spv, "spv"),
+        (settings.emit_asm, "spvasm"),
+        (settings.emit_c_header, "h"),
+        (settings.flatten_includes, flat_extension.as_str()),
+        (settings.source_map, "map.json"),
+        (settings.emit_deps_json, "deps.json"),
+        (settings.emit_reflection, "reflect.json"),
+    ]
+    .iter()
+    .filter(|(emitted, _)| *emitted)
+    .map(|(_, extension)| output_name(extension))
+    .collect();
+
+    // compile
+    let file_name = path
+        .to_str()
+        .ok_or_else(|| CompilerError::NonUnicodePath(path.display().to_string()))?;
+
+    // skip shaders whose outputs are newer than their sources
+    if settings.skip_unchanged
+        && settings.bench.is_none()
+        && up_to_date(path, &curr_shader, &output_names, settings)
+    {
+        info!(
+            "Skipped {} ({}), because its outputs are up to date",
+            path.display(),
+            stage_extension(kind, settings)
+        );
+        stats.written.extend(output_names);
+        return Ok(());
+    }
+    let translate = |e: shaderc::Error| {
+        CompilerError::Compilation(translate_error(
+            &e.to_string(),
+            file_name,
+            &line_mapping,
+            &added_lines,
+        ))
+    };
+    // every compilation starts without included files
+    let reset_included = || included.borrow_mut().clear();
+
+    // time repeated compilations, without writing anything
+    if let Some(runs) = settings.bench {
+        let mut times = Vec::with_capacity(runs);
+        for _ in 0..runs {
+            reset_included();
+            let start = Instant::now();
+            compiler
+                .compile_into_spirv(&curr_shader, kind, file_name, entry_point, Some(options))
+                .map_err(translate)?;
+            times.push(start.elapsed());
+        }
+        let label = match &build.name {
+            Some(variant) => format!(
+                "{} ({}, {})",
+                path.display(),
+                stage_extension(kind, settings),
+                variant
+            ),
+            None => format!("{} ({})", path.display(), stage_extension(kind, settings)),
+        };
+        stats.bench.push((label, times));
+        return Ok(());
+    }
+
+    let unoptimized_options;
+    reset_included();
+    let compiled = match compiler.compile_into_spirv(
+        &curr_shader,
+        kind,
+        file_name,
+        entry_point,
+        Some(options),
+    ) {
+        Ok(out) => Ok((out, options)),
+        // retry without optimization, in case the optimizer is at fault
+        Err(e) if settings.retry_on_failure && level != shaderc::OptimizationLevel::Zero => {
+            let mut o = options.clone().expect("Couldn't clone shader options.");
+            o.set_optimization_level(shaderc::OptimizationLevel::Zero);
+            unoptimized_options = o;
+            reset_included();
+            match compiler.compile_into_spirv(
+                &curr_shader,
+                kind,
+                file_name,
+                entry_point,
+                Some(&unoptimized_options),
+            ) {
+                Ok(out) => {
+                    warn!(
+                        "Compiling {} ({:?}) failed with optimization level {:?}; optimization was disabled for this shader.",
+                        path.display(),
+                        kind,
+                        level
+                    );
+                    level = shaderc::OptimizationLevel::Zero;
+                    Ok((out, &unoptimized_options))
+                }
+                Err(_) => Err(e),
+            }
+        }
+        Err(e) => Err(e),
+    };
+    let (out, options) = match compiled {
+        Ok(compiled) => compiled,
+        Err(e) => {
+            if settings.dump_on_error {
+                reset_included();
+                dump_failed_source(compiler, &curr_shader, path, kind, options, settings);
+            }
+            return Err(translate(e));
+        }
+    };
+
+    // the files included by this compilation, before another one resets them
+    let dependencies: Vec<PathBuf> = included.borrow().values().cloned().collect();
+
+    let has_warnings = out.get_num_warnings() != 0;
+    if has_warnings {
+        stats.warnings += out.get_num_warnings();
+        let messages = printed_diagnostics(&out.get_warning_messages(), settings.diagnostic_level);
+        if !messages.is_empty() {
+            match &build.label {
+                Some(label) => warn!("[{}] {}", label, messages),
+                None => warn!("{}", messages),
+            }
+        }
+    }
+    stats.compiled.push(CompiledShader {
+        path: path.to_path_buf(),
+        stage: stage_extension(kind, settings),
+        variant: build.name.clone(),
+        warnings: out
+            .get_warning_messages()
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(String::from)
+            .collect(),
+        size: None,
+        outputs: Vec::new(),
+    });
+
+    // compile to assembly and assemble that instead
+    let out = if settings.assembly_roundtrip {
+        reset_included();
+        let asm = compiler
+            .compile_into_spirv_assembly(&curr_shader, kind, file_name, entry_point, Some(options))
+            .map_err(translate)?;
+        let assembled = compiler
+            .assemble(&asm.as_text(), Some(options))
+            .map_err(|e| CompilerError::Compilation(e.to_string()))?;
+        if assembled.as_binary() != out.as_binary() {
+            warn!(
+                "The binary assembled from the SPIR-V assembly of {} ({}) differs from the compiled one",
+                path.display(),
+                stage_extension(kind, settings)
+            );
+        }
+        assembled
+    } else {
+        out
+    };
+
+    // size budget
+    let size = out.as_binary_u8().len();
+    if let Some(shader) = stats.compiled.last_mut() {
+        shader.size = Some(size);
+    }
+    let label = build
+        .label
+        .as_ref()
+        .map_or_else(String::new, |label| format!(", {}", label));
+    info!(
+        "Compiled {} ({}{}) to {} bytes",
+        path.display(),
+        stage_extension(kind, settings),
+        label,
+        size
+    );
+    if let Some(max_size) = settings.max_size {
+        if size > max_size {
+            return Err(CompilerError::TooLarge(
+                path.display().to_string(),
+                stage_extension(kind, settings),
+                size,
+                max_size,
+            ));
+        }
+    }
+
+    if settings.module_info {
+        print_module_info(path, kind, out.as_binary(), settings);
+    }
+    if settings.instr_count {
+        print_instruction_count(path, kind, out.as_binary(), settings, stats);
+    }
+    if settings.validate && kind == shaderc::ShaderKind::Geometry {
+        validate_geometry(path, out.as_binary(), stats).map_err(|message| {
+            CompilerError::Validation(
+                path.display().to_string(),
+                stage_extension(kind, settings),
+                message,
+            )
+        })?;
+    }
+    if settings
+        .reflection_schema
+        .iter()
+        .any(|required| required.kind == kind)
+    {
+        verify_reflection(kind, out.as_binary(), &settings.reflection_schema).map_err(
+            |message| {
+                CompilerError::Validation(
+                    path.display().to_string(),
+                    stage_extension(kind, settings),
+                    message,
+                )
+            },
+        )?;
+    }
+    if settings.reflect {
+        print_reflection(path, kind, out.as_binary(), settings);
+    }
+    // optimization may strip unused parts of the interface
+    if checks_stripped_interface(settings.reflect, settings.validate, level) {
+        let mut o = options.clone().expect("Couldn't clone shader options.");
+        o.set_optimization_level(shaderc::OptimizationLevel::Zero);
+        reset_included();
+        if let Ok(unoptimized) =
+            compiler.compile_into_spirv(&curr_shader, kind, file_name, entry_point, Some(&o))
+        {
+            warn_stripped_interface(path, kind, out.as_binary(), unoptimized.as_binary());
+        }
+    }
+
+    // save CompliationArtifact
+    if settings.output.is_none() {
+        return Ok(());
+    }
+    let mut written_spv = None;
+    if settings.emit_spv && settings.no_binary_on_warning && has_warnings {
+        warn!(
+            "Not writing {}, because {} ({}) has warnings",
+            output_name("spv"),
+            path.display(),
+            stage_extension(kind, settings)
+        );
+        stats.withheld.push(output_name("spv"));
+    } else if settings.emit_spv {
+        let binary: Vec<u8> = if settings.output_bytes_as_u32 {
+            out.as_binary()
+                .iter()
+                .flat_map(|word| word.to_le_bytes())
+                .collect()
+        } else {
+            out.as_binary_u8().to_vec()
+        };
+        // content-addressed name, e.g. foo-frag.a1b2c3d4.spv
+        let spv_name = if settings.emit_hash {
+            let hashed = output_name(&format!("{}.spv", content_hash(&binary)));
+            stats.hashed.insert(output_name("spv"), hashed.clone());
+            hashed
+        } else {
+            output_name("spv")
+        };
+        write_output(settings, stats, spv_name.clone(), &binary);
+        written_spv = Some(spv_name.clone());
+
+        // external tool, e.g. an optimizer or signer
+        if let (Some(command), Some(Output::Directory(output_dir))) =
+            (&settings.post_process, &settings.output)
+        {
+            post_process(
+                command,
+                &output_dir.join(&spv_name),
+                path,
+                &stage_extension(kind, settings),
+                entry_point,
+            )
+            .map_err(|message| CompilerError::PostProcess(path.display().to_string(), message))?;
+        }
+    }
+
+    // save assembly
+    if settings.emit_asm {
+        reset_included();
+        let asm = compiler
+            .compile_into_spirv_assembly(&curr_shader, kind, file_name, entry_point, Some(options))
+            .map_err(|e| CompilerError::Compilation(e.to_string()))?;
+        let mut text = asm.as_text();
+        if settings.emit_metadata_header {
+            let header = format!(
+                "; Source: {}\n; Stage: {}\n; Entry point: {}\n; Optimization: {}\n; Generated by ve_shader {}\n",
+                path.display(),
+                stage_extension(kind, settings),
+                entry_point,
+                table_name(OPTIMIZATION_LEVELS, level),
+                env!("CARGO_PKG_VERSION")
+            );
+            text.insert_str(0, &header);
+        }
+        write_output(settings, stats, output_name("spvasm"), text.as_bytes());
+    }
+
+    // save C header
+    if settings.emit_c_header {
+        let identifier = match &build.name {
+            Some(variant) => format!("{}_{}_{}", output_folder, output_extension, variant),
+            None => format!("{}_{}", output_folder, output_extension),
+        };
+        let identifier = c_identifier(&identifier);
+        let header = c_header(path, &identifier, out.as_binary());
+        write_output(settings, stats, output_name("h"), header.as_bytes());
+    }
+
+    // save source with inlined includes
+    if settings.flatten_includes {
+        let flattened = flatten_includes(
+            &curr_shader,
+            file_name,
+            &include_dirs(path, settings),
+            &mut HashSet::new(),
+            settings,
+            0,
+        )
+        .map_err(CompilerError::Compilation)?;
+        write_output(
+            settings,
+            stats,
+            output_name(&flat_extension),
+            flattened.as_bytes(),
+        );
+    }
+
+    // save source map
+    if settings.source_map {
+        let stage = stage_extension(kind, settings);
+        let map = source_map(path, &stage, &line_mapping, added_statements);
+        write_output(settings, stats, output_name("map.json"), map.as_bytes());
+    }
+
+    // save dependencies
+    if settings.emit_deps_json {
+        let outputs = deps_outputs(
+            written_spv,
+            settings.emit_asm,
+            settings.emit_c_header,
+            output_name,
+        );
+        let deps = deps_json(&outputs, path, &dependencies);
+        write_output(settings, stats, output_name("deps.json"), deps.as_bytes());
+    }
+
+    // save reflection
+    if settings.emit_reflection {
+        let module = spirv::Module::parse(out.as_binary())
+            .map_err(|err| CompilerError::Compilation(err.to_string()))?;
+        let reflection = reflection_json(&module, &stage_extension(kind, settings));
+        write_output(
+            settings,
+            stats,
+            output_name("reflect.json"),
+            reflection.as_bytes(),
+        );
+    }
+    Ok(())
+}
+
+/// Runs the --post-process command on a written SPIR-V file
+///
+/// The command is split at whitespace and gets the file as its last argument; the source, stage
+/// and entry point are passed in environment variables.
+fn post_process(
+    command: &str,
+    output: &Path,
+    source: &Path,
+    stage: &str,
+    entry_point: &str,
+) -> Result<(), String> {
+    let mut words = command.split_whitespace();
+    let program = words.next().ok_or("Empty command")?;
+    debug!("Running {} on {}", command, output.display());
+    let status = Process::new(program)
+        .args(words)
+        .arg(output)
+        .env("VE_SHADER_SOURCE", source)
+        .env("VE_SHADER_STAGE", stage)
+        .env("VE_SHADER_ENTRY_POINT", entry_point)
+        .status()
+        .map_err(|err| format!("couldn't run {}: {}", program, err))?;
+    if !status.success() {
+        return Err(format!("{} exited with {}", command, status));
+    }
+    Ok(())
+}
+
+/// Whether all outputs of a shader are newer than its file, sidecar file and included files
+///
+/// The includes are found by scanning the source like --flatten-includes; if that fails, the
+/// shader is compiled to report the error. Only outputs in an output directory are checked.
+fn up_to_date(path: &Path, source: &str, outputs: &[String], settings: &Settings) -> bool {
+    let output_dir = match &settings.output {
+        Some(Output::Directory(output_dir)) => output_dir,
+        _ => return false,
+    };
+    let modified = |path: &Path| fs::metadata(path).and_then(|meta| meta.modified()).ok();
+
+    let oldest_output = outputs
+        .iter()
+        .map(|name| modified(&output_dir.join(name)))
+        .collect::<Option<Vec<_>>>()
+        .and_then(|times| times.into_iter().min());
+    let oldest_output = match oldest_output {
+        Some(time) => time,
+        None => return false,
+    };
+
+    let mut sources = HashSet::new();
+    if flatten_includes(
+        source,
+        &path.to_string_lossy(),
+        &include_dirs(path, settings),
+        &mut sources,
+        settings,
+        0,
+    )
+    .is_err()
+    {
+        return false;
+    }
+    sources.insert(path.to_path_buf());
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".toml");
+    if Path::new(&sidecar).is_file() {
+        sources.insert(PathBuf::from(sidecar));
+    }
+
+    sources
+        .iter()
+        .all(|source| modified(source).is_some_and(|time| time < oldest_output))
+}
+
+/// Writes the preprocessed source of a shader that failed to compile, for debugging
+///
+/// The dump is placed in the output directory, or next to the input file if there is none.
+fn dump_failed_source(
+    compiler: &mut shaderc::Compiler,
+    curr_shader: &str,
+    path: &Path,
+    kind: shaderc::ShaderKind,
+    options: &shaderc::CompileOptions,
+    settings: &Settings,
+) {
+    let file_name = path.to_string_lossy();
+    // fall back to the assembled source, if the preprocessor fails as well
+    let source = match compiler.preprocess(curr_shader, &file_name, "main", Some(options)) {
+        Ok(preprocessed) => preprocessed.as_text(),
+        Err(_) => String::from(curr_shader),
+    };
+
+    let dir = match &settings.output {
+        Some(Output::Directory(output_path)) => output_path.as_path(),
+        _ => path.parent().unwrap_or_else(|| Path::new("")),
+    };
+    let extension = format!("failed.{}", source_extension(settings.language));
+    let dump = dir.join(settings.naming.file_name(
+        &path.file_stem().expect("Invalid path").to_string_lossy(),
+        &stage_extension(kind, settings),
+        &extension,
+    ));
+    match fs::write(&dump, source) {
+        Ok(()) => info!(
+            "Wrote the source that failed to compile to {}",
+            dump.display()
+        ),
+        Err(err) => error!("Couldn't write {}: {}", dump.display(), err),
+    }
+}
+
+/// Writes an output file to the output directory, or keeps it for the archive
+fn write_output(settings: &Settings, stats: &mut Stats, name: String, contents: &[u8]) {
+    match &settings.output {
+        Some(Output::Directory(output_path)) => {
+            std::fs::write(output_path.join(&name), contents).expect("Unable to write file");
+        }
+        Some(Output::Archive(_)) => {
+            stats.archived.insert(name.clone(), contents.to_vec());
+        }
+        None => return,
+    }
+    if let Some(shader) = stats.compiled.last_mut() {
+        shader.outputs.push(name.clone());
+    }
+    stats.written.push(name);
+}
+
+/// Turns `layout(constant_id = N) const T name = default;` into `const T name = value;`
+///
+/// Only declarations on a single line of the compiled file are replaced, so line numbers stay
+/// the same; constants in included files keep their default. The ids of the replaced constants
+/// are added to `specialized`.
+fn specialize(
+    source: &str,
+    spec_constants: &[SpecConstant],
+    specialized: &mut HashSet<u32>,
+) -> String {
+    let reg = regex::Regex::new(
+        r"layout\s*\(\s*constant_id\s*=\s*([0-9]+)\s*\)\s*const\s+(\w+)\s+(\w+)\s*=[^;\n]*;",
+    )
+    .expect("Failed specialization: invalid regex");
+    reg.replace_all(source, |captures: &regex::Captures| {
+        let id: u32 = captures[1].parse().unwrap_or(u32::MAX);
+        match spec_constants.iter().find(|constant| constant.id == id) {
+            Some(constant) => {
+                debug!(
+                    "Specialized constant {} ({}) to {}",
+                    &captures[3], id, constant.value
+                );
+                specialized.insert(id);
+                format!(
+                    "const {} {} = {};",
+                    &captures[2], &captures[3], constant.value
+                )
+            }
+            None => captures[0].to_owned(),
+        }
+    })
+    .into_owned()
+}
+
+/// Stage name used in output file names
+fn stage_extension(kind: shaderc::ShaderKind, settings: &Settings) -> String {
+    settings
+        .stage_extensions
+        .iter()
+        .find(|(stage, _)| *stage == kind)
+        .map(|(_, extension)| extension.clone())
+        .unwrap_or_else(|| get_shader_kind_extension(kind).expect("Invalid output file extension"))
+}
+
+/// Turns a name into a valid C identifier
+fn c_identifier(name: &str) -> String {
+    let mut identifier: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if identifier.starts_with(|c: char| c.is_ascii_digit()) {
+        identifier.insert(0, '_');
+    }
+    identifier
+}
+
+/// Generates a C header, which embeds the SPIR-V words as an array
+fn c_header(path: &Path, identifier: &str, words: &[u32]) -> String {
+    let mut header = format!(
+        "// Generated by ve_shader from {}, do not edit.\n#pragma once\n\n#include <stdint.h>\n\nstatic const uint32_t {}[] = {{\n",
+        path.display(),
+        identifier
+    );
+    for line in words.chunks(8) {
+        let line: Vec<_> = line.iter().map(|word| format!("0x{:08x}", word)).collect();
+        header.push_str(&format!("    {},\n", line.join(", ")));
+    }
+    header.push_str("};\n");
+    header
+}
+
+/// Short hash of the contents of a file, for content-addressed names: 8 digits of FNV-1a
+///
+/// Unlike the hasher of the standard library, it doesn't change between Rust versions.
+fn content_hash(bytes: &[u8]) -> String {
+    let hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325_u64, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    format!("{:08x}", hash >> 32)
+}
+
+/// Generates a JSON source map from the lines of the compiled source to the lines of the input file
+///
+/// Lines added in front of the source (version, prelude) have no original line and are left out.
+fn source_map(path: &Path, stage: &str, line_mapping: &[usize], added_statements: usize) -> String {
+    let lines: Vec<_> = line_mapping
+        .iter()
+        .enumerate()
+        .map(|(idx, original)| format!("    \"{}\": {}", idx + added_statements + 1, original))
+        .collect();
+    format!(
+        "{{\n  \"source\": {},\n  \"stage\": {},\n  \"lines\": {{\n{}\n  }}\n}}\n",
+        json::string(&path.display().to_string()),
+        json::string(stage),
+        lines.join(",\n")
+    )
+}
+
+/// Names the compiled files of a shader for its deps JSON: the binary as written (with its hash, if
+/// any), or without a binary, the assembly and C header
+fn deps_outputs(
+    written_spv: Option<String>,
+    emit_asm: bool,
+    emit_c_header: bool,
+    output_name: impl Fn(&str) -> String,
+) -> Vec<String> {
+    if let Some(spv) = written_spv {
+        return vec![spv];
+    }
+    [(emit_asm, "spvasm"), (emit_c_header, "h")]
+        .iter()
+        .filter(|(emitted, _)| *emitted)
+        .map(|(_, extension)| output_name(extension))
+        .collect()
+}
+
+/// Generates a JSON object with the compiled files of a shader and the input files they were
+/// compiled from: the source, its sidecar file and includes
+fn deps_json(outputs: &[String], path: &Path, includes: &[PathBuf]) -> String {
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".toml");
+    let sidecar = PathBuf::from(sidecar);
+    let dependencies: Vec<_> = Some(&sidecar)
+        .filter(|sidecar| sidecar.is_file())
+        .into_iter()
+        .chain(includes)
+        .map(|dependency| format!("    {}", json::string(&dependency.display().to_string())))
+        .collect();
+    let outputs: Vec<_> = outputs.iter().map(|output| json::string(output)).collect();
+    format!(
+        "{{\n  \"outputs\": [{}],\n  \"source\": {},\n  \"dependencies\": [\n{}\n  ]\n}}\n",
+        outputs.join(", "),
+        json::string(&path.display().to_string()),
+        dependencies.join(",\n")
+    )
+}
+
+/// Generates a JSON object with the interface of a shader, like the output of `reflect`
+fn reflection_json(module: &spirv::Module, stage: &str) -> String {
+    let array = |items: Vec<String>| {
+        if items.is_empty() {
+            String::from("[]")
+        } else {
+            format!("[\n{}\n  ]", items.join(",\n"))
+        }
+    };
+    let entry_points = module
+        .entry_points
+        .iter()
+        .map(|entry_point| {
+            format!(
+                "    {{\"name\": {}, \"execution_model\": {}}}",
+                json::string(&entry_point.name),
+                json::string(&entry_point.execution_model.to_string())
+            )
+        })
+        .collect();
+    let variables = |variables: &[spirv::Variable]| {
+        variables
+            .iter()
+            .filter(|variable| !variable.built_in)
+            .map(|variable| {
+                format!(
+                    "    {{\"name\": {}, \"location\": {}}}",
+                    json::string(&variable.name),
+                    variable
+                        .location
+                        .map_or_else(|| String::from("null"), |location| location.to_string())
+                )
+            })
+            .collect()
+    };
+    let bindings = module
+        .bindings
+        .iter()
+        .map(|binding| {
+            format!(
+                "    {{\"set\": {}, \"binding\": {}, \"name\": {}, \"kind\": {}}}",
+                binding.set,
+                binding.binding,
+                json::string(&binding.name),
+                json::string(&binding.kind.to_string())
+            )
+        })
+        .collect();
+    let push_constants = module
+        .push_constants
+        .iter()
+        .map(|name| format!("    {}", json::string(name)))
+        .collect();
+    format!(
+        "{{\n  \"stage\": {},\n  \"spirv_version\": \"{}.{}\",\n  \"entry_points\": {},\n  \"inputs\": {},\n  \"outputs\": {},\n  \"bindings\": {},\n  \"push_constants\": {}\n}}\n",
+        json::string(stage),
+        module.version.0,
+        module.version.1,
+        array(entry_points),
+        array(variables(&module.inputs)),
+        array(variables(&module.outputs)),
+        array(bindings),
+        array(push_constants)
+    )
+}
+
+/// Prints a one-line summary of a compiled shader
+fn print_module_info(path: &Path, kind: shaderc::ShaderKind, binary: &[u32], settings: &Settings) {
+    let module = match spirv::Module::parse(binary) {
+        Ok(module) => module,
+        Err(err) => {
+            error!(
+                "Couldn't read the module info of {}: {}",
+                path.display(),
+                err
+            );
+            return;
+        }
+    };
+
+    let entry_points: Vec<_> = module
+        .entry_points
+        .iter()
+        .map(|entry_point| format!("{} ({})", entry_point.name, entry_point.execution_model))
+        .collect();
+    let capabilities: Vec<_> = module
+        .capabilities
+        .iter()
+        .map(|capability| capability.to_string())
+        .collect();
+    output!(
+        settings,
+        "{} ({}): SPIR-V {}.{}, entry points: {}, capabilities: {}",
+        path.display(),
+        get_shader_kind_extension(kind).unwrap_or_default(),
+        module.version.0,
+        module.version.1,
+        entry_points.join(", "),
+        capabilities.join(", ")
+    );
+}
+
+/// Checks the vertices a geometry shader emits against its output layout
+fn validate_geometry(path: &Path, binary: &[u32], stats: &mut Stats) -> Result<(), String> {
+    let geometry = spirv::Module::parse(binary)
+        .map_err(|err| err.to_string())?
+        .geometry;
+    for message in geometry_warnings(&geometry) {
+        warn!("{}: {}", path.display(), message);
+        stats.warnings += 1;
+        if let Some(shader) = stats.compiled.last_mut() {
+            shader.warnings.push(message);
+        }
+    }
+    Ok(())
+}
+
+/// Compares the EmitVertex instructions of a geometry shader with its output layout
+///
+/// The instructions are counted, not the paths through the shader: emits in different branches
+/// add up, and emits in a function called several times count once. So the counts only give
+/// warnings, and are skipped entirely for shaders with loops.
+fn geometry_warnings(geometry: &spirv::Geometry) -> Vec<String> {
+    if geometry.emit_vertex == 0 {
+        return vec![String::from(
+            "the geometry shader never calls EmitVertex(), so it outputs nothing",
+        )];
+    }
+    if geometry.loops {
+        return Vec::new();
+    }
+
+    let mut warnings = Vec::new();
+    if let Some(max_vertices) = geometry.max_vertices {
+        if geometry.emit_vertex > max_vertices as usize {
+            warnings.push(format!(
+                "EmitVertex() appears {} times, but the output layout declares max_vertices = {}",
+                geometry.emit_vertex, max_vertices
+            ));
+        }
+    }
+    if let Some(per_primitive) = geometry.vertices_per_primitive {
+        if geometry.emit_vertex < per_primitive as usize {
+            warnings.push(format!(
+                "EmitVertex() appears {} times, but a primitive of the output layout needs {} vertices",
+                geometry.emit_vertex, per_primitive
+            ));
+        }
+    }
+    warnings
+}
+
+/// Prints the number of instructions of a compiled shader and adds it to the total
+fn print_instruction_count(
+    path: &Path,
+    kind: shaderc::ShaderKind,
+    binary: &[u32],
+    settings: &Settings,
+    stats: &mut Stats,
+) {
+    let count = match spirv::Module::parse(binary) {
+        Ok(module) => module.instruction_count,
+        Err(err) => {
+            error!(
+                "Couldn't count the instructions of {}: {}",
+                path.display(),
+                err
+            );
+            return;
+        }
+    };
+    output!(
+        settings,
+        "{} ({}): {}",
+        path.display(),
+        get_shader_kind_extension(kind).unwrap_or_default(),
+        instruction_count(count)
+    );
+    stats.instructions.add(count);
+    stats.counted_shaders += 1;
+}
+
+fn instruction_count(count: spirv::InstructionCount) -> String {
+    format!(
+        "{} instructions ({} arithmetic, {} memory, {} other)",
+        count.total,
+        count.arithmetic,
+        count.memory,
+        count.total - count.arithmetic - count.memory
+    )
+}
+
+/// Whether to compare the interface of a shader with an unoptimized build, which `reflect` and
+/// `--validate` do; without optimization, nothing is stripped
+fn checks_stripped_interface(
+    reflect: bool,
+    validate: bool,
+    level: shaderc::OptimizationLevel,
+) -> bool {
+    (reflect || validate) && level != shaderc::OptimizationLevel::Zero
+}
+
+/// Warns about inputs, outputs and bindings that the optimizer removed
+fn warn_stripped_interface(
+    path: &Path,
+    kind: shaderc::ShaderKind,
+    optimized: &[u32],
+    unoptimized: &[u32],
+) {
+    // (identity, description) of each part; the identity doesn't rely on names where possible
+    let interface = |binary: &[u32]| -> Vec<(String, String)> {
+        let module = match spirv::Module::parse(binary) {
+            Ok(module) => module,
+            Err(_) => return Vec::new(),
+        };
+        let mut interface = Vec::new();
+        for (direction, variables) in &[("input", &module.inputs), ("output", &module.outputs)] {
+            for variable in variables.iter().filter(|variable| !variable.built_in) {
+                let identity = match variable.location {
+                    Some(location) => format!("{} location {}", direction, location),
+                    None => format!("{} {}", direction, variable.name),
+                };
+                interface.push((identity, format!("{} {}", direction, variable.name)));
+            }
+        }
+        for binding in &module.bindings {
+            let identity = format!("set {} binding {}", binding.set, binding.binding);
+            let description = format!("{} ({})", identity, binding.name);
+            interface.push((identity, description));
+        }
+        interface
+    };
+
+    let optimized = interface(optimized);
+    let stripped: Vec<_> = interface(unoptimized)
+        .into_iter()
+        .filter(|(identity, _)| !optimized.iter().any(|(other, _)| other == identity))
+        .map(|(_, description)| description)
+        .collect();
+    if !stripped.is_empty() {
+        warn!(
+            "Optimization removed parts of the interface of {} ({}): {}",
+            path.display(),
+            get_shader_kind_extension(kind).unwrap_or_default(),
+            stripped.join(", ")
+        );
+    }
+}
+
+/// Prints the interface of a compiled shader
+fn print_reflection(path: &Path, kind: shaderc::ShaderKind, binary: &[u32], settings: &Settings) {
+    let module = match spirv::Module::parse(binary) {
+        Ok(module) => module,
+        Err(err) => {
+            error!("Couldn't reflect {}: {}", path.display(), err);
+            return;
+        }
+    };
+
+    let stage = get_shader_kind_extension(kind).unwrap_or_default();
+    output!(
+        settings,
+        "{} ({}, SPIR-V {}.{})",
+        path.display(),
+        stage,
+        module.version.0,
+        module.version.1
+    );
+    for entry_point in &module.entry_points {
+        output!(
+            settings,
+            "  entry point {} ({})",
+            entry_point.name,
+            entry_point.execution_model
+        );
+    }
+    for (direction, variables) in &[("input", &module.inputs), ("output", &module.outputs)] {
+        for variable in variables.iter().filter(|variable| !variable.built_in) {
+            match variable.location {
+                Some(location) => {
+                    output!(
+                        settings,
+                        "  {} location {}: {}",
+                        direction,
+                        location,
+                        variable.name
+                    )
+                }
+                None => output!(settings, "  {}: {}", direction, variable.name),
+            }
+        }
+    }
+    for binding in &module.bindings {
+        output!(
+            settings,
+            "  set {} binding {}: {} ({})",
+            binding.set,
+            binding.binding,
+            binding.name,
+            binding.kind
+        );
+    }
+    for push_constant in &module.push_constants {
+        output!(settings, "  push constant: {}", push_constant);
+    }
+}
+
+/// Checks that a shader has the descriptor bindings the schema requires of its stage
+fn verify_reflection(
+    kind: shaderc::ShaderKind,
+    binary: &[u32],
+    schema: &[RequiredBinding],
+) -> Result<(), String> {
+    let module = spirv::Module::parse(binary).map_err(|err| err.to_string())?;
+    let mut problems = Vec::new();
+    for required in schema.iter().filter(|required| required.kind == kind) {
+        let found = module
+            .bindings
+            .iter()
+            .find(|binding| binding.set == required.set && binding.binding == required.binding);
+        match (found, required.descriptor) {
+            (None, _) => problems.push(format!(
+                "set {} binding {} is missing",
+                required.set, required.binding
+            )),
+            (Some(binding), Some(descriptor)) if binding.kind != descriptor => {
+                problems.push(format!(
+                    "set {} binding {} ({}) is a {}, expected a {}",
+                    required.set, required.binding, binding.name, binding.kind, descriptor
+                ))
+            }
+            _ => {}
+        }
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems.join("; "))
+    }
+}
+
+/// Name of the `#version` line added in front of a shader, in errors
+const ADDED_VERSION: &str = "<#version added by ve_shader>";
+
+/// Lines added in front of the source of a shader before compiling it
+#[derive(Default)]
+struct AddedLines<'a> {
+    /// The `#version` line, which comes first
+    version: bool,
+    /// Path and number of lines of the --prelude file
+    prelude: Option<(&'a Path, usize)>,
+}
+
+/// Replaces error lines from what the parser saw to what is actually used in the input file
+///
+/// Only diagnostics of the compiled file are remapped, since `line_mapping` doesn't apply to
+/// included files, which report their own line numbers. Each diagnostic is rewritten to
+/// `file:line:col: message`, keeping the column if the compiler reported one. Errors in the added
+/// lines name the prelude file or the added `#version` line instead. If a line can't be remapped,
+/// the untranslated error is returned with a note.
+fn translate_error(
+    error: &str,
+    file_name: &str,
+    line_mapping: &[usize],
+    added_lines: &AddedLines,
+) -> String {
+    let reg = regex::Regex::new(&format!(
+        "{}:([0-9]+):(?:([0-9]+):)?[ \\t]*([^\\n]*)",
+        regex::escape(file_name)
+    ))
+    .expect("Failed error translation: invalid regex");
+    let remap = |captures: &regex::Captures| -> Result<String, String> {
+        let old_line: usize = captures[1]
+            .parse()
+            .map_err(|_| format!("invalid line number {}", &captures[1]))?;
+
+        // lines added in front of the source (version, prelude) don't exist in the input file
+        let line = if added_lines.version {
+            old_line.saturating_sub(1)
+        } else {
+            old_line
+        };
+        let prelude_lines = added_lines.prelude.map_or(0, |(_, lines)| lines);
+        let (file, new_line) = match added_lines.prelude {
+            _ if added_lines.version && old_line == 1 => (String::from(ADDED_VERSION), 1),
+            Some((prelude_path, _)) if line >= 1 && line <= prelude_lines => {
+                (prelude_path.display().to_string(), line)
+            }
+            _ => (
+                String::from(file_name),
+                *line
+                    .checked_sub(prelude_lines + 1)
+                    .and_then(|idx| line_mapping.get(idx))
+                    .ok_or_else(|| format!("no input line for line {}", old_line))?,
+            ),
+        };
+        let message = captures[3].trim_end();
+        Ok(match captures.get(2) {
+            Some(column) => format!("{}:{}:{}: {}", file, new_line, column.as_str(), message),
+            None => format!("{}:{}: {}", file, new_line, message),
+        })
+    };
+
+    // if any line can't be remapped, none of them can be trusted
+    let mut failure = None;
+    let translated = reg.replace_all(error, |captures: &regex::Captures| {
+        remap(captures).unwrap_or_else(|reason| {
+            failure.get_or_insert(reason);
+            captures[0].to_owned()
+        })
+    });
+    match failure {
+        Some(reason) => format!(
+            "{}\nline remapping unavailable ({}); the line numbers refer to the compiled source",
+            error.trim_end(),
+            reason
+        ),
+        None => translated.into_owned(),
+    }
+}
+
+/// Accepted stages of the TYPE instruction
+const SHADER_KINDS: &[(&str, shaderc::ShaderKind)] = &[
+    ("VERTEX", shaderc::ShaderKind::Vertex),
+    ("FRAGMENT", shaderc::ShaderKind::Fragment),
+    ("GEOMETRY", shaderc::ShaderKind::Geometry),
+    ("COMPUTE", shaderc::ShaderKind::Compute),
+];
+
+/// Converts a &str to shaderc::ShaderKind
+pub fn parse_shader_kind(identifier: &str) -> Option<shaderc::ShaderKind> {
+    SHADER_KINDS
+        .iter()
+        .find(|(name, _)| *name == identifier)
+        .map(|&(_, kind)| kind)
+}
+
+/// Converts a &str to shaderc::ShaderKind
+pub fn get_shader_kind_extension(kind: shaderc::ShaderKind) -> Option<String> {
+    use shaderc::ShaderKind::*;
+    Some(match kind {
+        Vertex => String::from("vert"),
+        Fragment => String::from("frag"),
+        Geometry => String::from("geo"),
+        Compute => String::from("comp"),
+        _ => {
+            return None;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn geometry_emit_counts() {
+        let geometry = |emit_vertex, loops| spirv::Geometry {
+            max_vertices: Some(3),
+            vertices_per_primitive: Some(3),
+            emit_vertex,
+            loops,
+        };
+
+        assert!(geometry_warnings(&geometry(3, false)).is_empty());
+        assert_eq!(geometry_warnings(&geometry(0, false)).len(), 1);
+        assert_eq!(geometry_warnings(&geometry(0, true)).len(), 1);
+        // could be an if/else emitting a triangle on either side
+        let warnings = geometry_warnings(&geometry(6, false));
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("max_vertices = 3"));
+        let warnings = geometry_warnings(&geometry(2, false));
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("needs 3 vertices"));
+        // a loop may run any number of times
+        assert!(geometry_warnings(&geometry(1, true)).is_empty());
+        assert!(geometry_warnings(&geometry(6, true)).is_empty());
+
+        let unbounded = spirv::Geometry {
+            emit_vertex: 1,
+            ..spirv::Geometry::default()
+        };
+        assert!(geometry_warnings(&unbounded).is_empty());
+    }
+
+    #[test]
+    fn block_hash_covers_entry_point_label_and_shared_stage() {
+        let block = || Block {
+            kind: shaderc::ShaderKind::Fragment,
+            more_kinds: Vec::new(),
+            source: String::from("void main() {}"),
+            line_mapping: vec![1],
+            version: None,
+            label: None,
+            entry_point: None,
+            shares_stage: false,
+        };
+        let front_matter = FrontMatter::default();
+        let hash = block_hash(&block(), &front_matter);
+        assert_eq!(block_hash(&block(), &front_matter), hash);
+
+        let changed = [
+            Block {
+                entry_point: Some(String::from("shadow")),
+                ..block()
+            },
+            Block {
+                label: Some(String::from("shadow pass")),
+                ..block()
+            },
+            Block {
+                shares_stage: true,
+                ..block()
+            },
+            Block {
+                more_kinds: vec![shaderc::ShaderKind::Vertex],
+                ..block()
+            },
+        ];
+        for block in &changed {
+            assert_ne!(block_hash(block, &front_matter), hash);
+        }
+    }
+
+    #[test]
+    fn directory_configs_stop_at_the_root() {
+        let root = std::env::temp_dir().join(format!("ve_shader_test_{}", std::process::id()));
+        let shaders = root.join("project").join("shaders");
+        fs::create_dir_all(&shaders).unwrap();
+        let root = fs::canonicalize(&root).unwrap();
+        let project = root.join("project");
+        let shaders = project.join("shaders");
+        for dir in &[&root, &project, &shaders] {
+            fs::write(dir.join(DEFAULT_CONFIG_FILE), "").unwrap();
+        }
+        let shader = shaders.join("foo.glsl");
+
+        let configs = directory_configs(&shader, &project, None);
+        let main_config = project.join(DEFAULT_CONFIG_FILE);
+        let configs_without_main = directory_configs(&shader, &project, Some(&main_config));
+        let outside = directory_configs(&root.join("foo.glsl"), &project, None);
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(
+            configs,
+            [
+                project.join(DEFAULT_CONFIG_FILE),
+                shaders.join(DEFAULT_CONFIG_FILE)
+            ]
+        );
+        assert_eq!(configs_without_main, [shaders.join(DEFAULT_CONFIG_FILE)]);
+        assert!(outside.is_empty());
+    }
+
+    #[test]
+    fn reflection_json_is_valid_json() {
+        let module = spirv::Module {
+            version: (1, 3),
+            entry_points: vec![spirv::EntryPoint {
+                name: String::from("main"),
+                execution_model: spirv::ExecutionModel::Fragment,
+            }],
+            inputs: vec![
+                spirv::Variable {
+                    name: String::from("uv"),
+                    location: Some(0),
+                    built_in: false,
+                },
+                spirv::Variable {
+                    name: String::from("gl_FragCoord"),
+                    location: None,
+                    built_in: true,
+                },
+            ],
+            bindings: vec![spirv::Binding {
+                set: 1,
+                binding: 2,
+                name: String::from("albedo"),
+                kind: spirv::DescriptorKind::CombinedImageSampler,
+            }],
+            push_constants: vec![String::from("constants")],
+            ..spirv::Module::default()
+        };
+
+        let reflection = json::parse(&reflection_json(&module, "frag")).unwrap();
+        assert_eq!(
+            reflection.strings(),
+            [
+                "frag",
+                "1.3",
+                "main",
+                "fragment",
+                "uv",
+                "albedo",
+                "combined_image_sampler",
+                "constants"
+            ]
+        );
+        let json::Value::Object(members) = reflection else {
+            panic!("expected an object");
+        };
+        let member = |key: &str| {
+            members
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value.clone())
+                .unwrap()
+        };
+        assert_eq!(member("outputs"), json::Value::Array(Vec::new()));
+        assert_eq!(
+            member("bindings"),
+            json::Value::Array(vec![json::Value::Object(vec![
+                (String::from("set"), json::Value::Number(1.0)),
+                (String::from("binding"), json::Value::Number(2.0)),
+                (
+                    String::from("name"),
+                    json::Value::String(String::from("albedo"))
+                ),
+                (
+                    String::from("kind"),
+                    json::Value::String(String::from("combined_image_sampler"))
+                ),
+            ])])
+        );
+    }
+
+    #[test]
+    fn specialize_replaces_matching_declarations() {
+        let spec_constants = [
+            SpecConstant {
+                id: 0,
+                value: String::from("16"),
+            },
+            SpecConstant {
+                id: 2,
+                value: String::from("true"),
+            },
+            SpecConstant {
+                id: 7,
+                value: String::from("1.5"),
+            },
+        ];
+        let source = "\
+layout(constant_id = 0) const int SAMPLES = 4;
+layout (constant_id=1) const float SCALE = 1.0;
+  layout( constant_id = 2 )  const bool FAST = false;  // comment
+layout(constant_id = 3)
+const uint SPLIT = 2;
+void main() {}";
+        let mut specialized = HashSet::new();
+        let result = specialize(source, &spec_constants, &mut specialized);
+
+        assert_eq!(
+            result,
+            "\
+const int SAMPLES = 16;
+layout (constant_id=1) const float SCALE = 1.0;
+  const bool FAST = true;  // comment
+layout(constant_id = 3)
+const uint SPLIT = 2;
+void main() {}"
+        );
+        // declarations across lines aren't replaced, and id 7 isn't declared
+        assert_eq!(specialized, [0, 2].iter().copied().collect());
+        assert_eq!(result.lines().count(), source.lines().count());
+    }
+
+    #[test]
+    fn braces() {
+        assert_eq!(expand_braces("shaders/*.glsl"), ["shaders/*.glsl"]);
+        assert_eq!(expand_braces("*.{vert,frag}"), ["*.vert", "*.frag"]);
+        assert_eq!(
+            expand_braces("*.{vert,frag}.{glsl,hlsl}"),
+            ["*.vert.glsl", "*.vert.hlsl", "*.frag.glsl", "*.frag.hlsl"]
+        );
+    }
+
+    #[test]
+    fn nested_braces() {
+        assert_eq!(
+            expand_braces("{ui/{menu,hud},world}/*.glsl"),
+            ["ui/menu/*.glsl", "ui/hud/*.glsl", "world/*.glsl"]
+        );
+        assert_eq!(expand_braces("{{a,b},c}"), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn empty_alternatives() {
+        assert_eq!(
+            expand_braces("foo{,.debug}.glsl"),
+            ["foo.glsl", "foo.debug.glsl"]
+        );
+        assert_eq!(expand_braces("{a,,b}"), ["a", "", "b"]);
+        // not alternatives, like in a shell
+        assert_eq!(expand_braces("foo{}.glsl"), ["foo{}.glsl"]);
+        assert_eq!(expand_braces("{a}{b,c}"), ["{a}b", "{a}c"]);
+    }
+
+    #[test]
+    fn unbalanced_braces() {
+        assert_eq!(expand_braces("foo{a,b"), ["foo{a,b"]);
+        assert_eq!(expand_braces("{a,b}{c"), ["a{c", "b{c"]);
+        assert_eq!(expand_braces("a}{b,c}"), ["a}b", "a}c"]);
+        assert_eq!(expand_braces("{a,{b,c}"), ["{a,{b,c}"]);
+    }
+
+    /// Keeps the lines of `source` that its conditions allow, like `parse`
+    fn apply_conditions<'a>(source: &'a str, defines: &[&str]) -> Result<Vec<&'a str>, String> {
+        let mut conditions = Conditions::default();
+        let mut kept = Vec::new();
+        for (idx, line) in source.lines().enumerate() {
+            if let Some(instruction) = split_instruction(line) {
+                let is_defined = |name: &str| defines.contains(&name);
+                if conditions
+                    .apply(instruction, idx + 1, is_defined)
+                    .map_err(|message| format!("{}: {}", idx + 1, message))?
+                {
+                    continue;
+                }
+            }
+            if conditions.active() {
+                kept.push(line);
+            }
+        }
+        match conditions.unclosed() {
+            Some(line) => Err(format!("{}: IF without a matching ENDIF", line)),
+            None => Ok(kept),
+        }
+    }
+
+    #[test]
+    fn conditions_keep_lines() {
+        let source = "a\n//# IF SHADOWS\nb\n//# ELSE\nc\n//# ENDIF\nd";
+        assert_eq!(
+            apply_conditions(source, &["SHADOWS"]),
+            Ok(vec!["a", "b", "d"])
+        );
+        assert_eq!(apply_conditions(source, &[]), Ok(vec!["a", "c", "d"]));
+        // other instructions are kept for the rest of the parser
+        assert_eq!(
+            apply_conditions("//# IF A\n//# TYPE VERTEX\n//# ENDIF", &["A"]),
+            Ok(vec!["//# TYPE VERTEX"])
+        );
+    }
+
+    #[test]
+    fn nested_conditions() {
+        let source = "\
+//# IF A
+a
+//# IF B
+ab
+//# ELSE
+a_not_b
+//# ENDIF
+//# ELSE
+not_a
+//# IF B
+not_a_b
+//# ENDIF
+//# ENDIF";
+        assert_eq!(apply_conditions(source, &["A", "B"]), Ok(vec!["a", "ab"]));
+        assert_eq!(apply_conditions(source, &["A"]), Ok(vec!["a", "a_not_b"]));
+        assert_eq!(
+            apply_conditions(source, &["B"]),
+            Ok(vec!["not_a", "not_a_b"])
+        );
+        assert_eq!(apply_conditions(source, &[]), Ok(vec!["not_a"]));
+    }
+
+    #[test]
+    fn condition_errors() {
+        assert_eq!(
+            apply_conditions("//# IF A\n//# ELSE\n//# ELSE\n//# ENDIF", &[]),
+            Err(String::from("3: ELSE after the ELSE of the same IF"))
+        );
+        assert_eq!(
+            apply_conditions("a\n//# ENDIF", &[]),
+            Err(String::from("2: ENDIF without a matching IF"))
+        );
+        assert_eq!(
+            apply_conditions("//# ELSE", &[]),
+            Err(String::from("1: ELSE without a matching IF"))
+        );
+        assert_eq!(
+            apply_conditions("//# IF", &[]),
+            Err(String::from("1: IF needs the name of a define"))
+        );
+        // the innermost open IF is named
+        assert_eq!(
+            apply_conditions("//# IF A\n//# IF B\n//# ENDIF\n//# IF C\na", &[]),
+            Err(String::from("4: IF without a matching ENDIF"))
+        );
+    }
+
+    #[test]
+    fn instructions() {
+        assert_eq!(
+            split_instruction("//# TYPE VERTEX"),
+            Some(("TYPE", "VERTEX"))
+        );
+        assert_eq!(
+            split_instruction("//#TYPE VERTEX"),
+            Some(("TYPE", "VERTEX"))
+        );
+        assert_eq!(
+            split_instruction("  //#\tTYPE\tVERTEX  "),
+            Some(("TYPE", "VERTEX"))
+        );
+        assert_eq!(
+            split_instruction("//#   LABEL    \"shadow   pass\""),
+            Some(("LABEL", "\"shadow   pass\""))
+        );
+        assert_eq!(
+            split_instruction("//# TYPE VERTEX FRAGMENT"),
+            Some(("TYPE", "VERTEX FRAGMENT"))
+        );
+        assert_eq!(
+            split_instruction("vec4 color; //# ENTRY main"),
+            Some(("ENTRY", "main"))
+        );
+    }
+
+    #[test]
+    fn instructions_without_value() {
+        assert_eq!(split_instruction("//# ENDIF"), Some(("ENDIF", "")));
+        assert_eq!(split_instruction("//#ELSE   "), Some(("ELSE", "")));
+        assert_eq!(split_instruction("//#"), None);
+        assert_eq!(split_instruction("//#  \t"), None);
+        assert_eq!(split_instruction("// TYPE VERTEX"), None);
+    }
+
+    #[test]
+    fn if_takes_a_single_define() {
+        assert_eq!(
+            apply_conditions("//# IF FOO BAR\n//# ENDIF", &["FOO"]),
+            Err(String::from("1: IF takes the name of a single define"))
+        );
+        assert_eq!(
+            apply_conditions("//#IF\tFOO \na\n//#ENDIF", &["FOO"]),
+            Ok(vec!["a"])
+        );
+    }
+
+    #[test]
+    fn deps_without_a_binary() {
+        let output_name = |extension: &str| format!("foo-frag.{}", extension);
+
+        // --emit deps
+        assert!(deps_outputs(None, false, false, output_name).is_empty());
+        // --emit asm,deps or --asm-only
+        assert_eq!(
+            deps_outputs(None, true, false, output_name),
+            ["foo-frag.spvasm"]
+        );
+        assert_eq!(
+            deps_outputs(None, true, true, output_name),
+            ["foo-frag.spvasm", "foo-frag.h"]
+        );
+
+        let deps = json::parse(&deps_json(&[], Path::new("foo.glsl"), &[])).unwrap();
+        assert_eq!(
+            deps,
+            json::Value::Object(vec![
+                (String::from("outputs"), json::Value::Array(Vec::new())),
+                (
+                    String::from("source"),
+                    json::Value::String(String::from("foo.glsl"))
+                ),
+                (String::from("dependencies"), json::Value::Array(Vec::new())),
+            ])
+        );
+    }
+
+    #[test]
+    fn deps_name_the_hashed_binary() {
+        let output_name = |extension: &str| format!("foo-frag.{}", extension);
+        let outputs = deps_outputs(
+            Some(String::from("foo-frag.a1b2c3d4.spv")),
+            true,
+            false,
+            output_name,
+        );
+        assert_eq!(outputs, ["foo-frag.a1b2c3d4.spv"]);
+
+        let deps = deps_json(
+            &outputs,
+            Path::new("foo.glsl"),
+            &[PathBuf::from("common.glsl")],
+        );
+        assert_eq!(
+            json::parse(&deps).unwrap().strings(),
+            ["foo-frag.a1b2c3d4.spv", "foo.glsl", "common.glsl"]
+        );
+    }
+
+    #[test]
+    fn errors_map_to_the_input_file() {
+        // a line that can't be remapped discards the whole translation
+        let error = "foo.glsl:2: error: a\nfoo.glsl:3:12: error: b\n";
+        assert_eq!(
+            translate_error(error, "foo.glsl", &[4, 9], &AddedLines::default()),
+            "foo.glsl:2: error: a\nfoo.glsl:3:12: error: b\nline remapping unavailable (no input line for line 3); the line numbers refer to the compiled source"
+        );
+        assert_eq!(
+            translate_error(
+                "foo.glsl:1: error: a\nfoo.glsl:2:5: error: b\n",
+                "foo.glsl",
+                &[4, 9],
+                &AddedLines::default()
+            ),
+            "foo.glsl:4: error: a\nfoo.glsl:9:5: error: b\n"
+        );
+    }
+
+    #[test]
+    fn errors_in_the_prelude_name_the_prelude() {
+        let prelude = Path::new("shaders/prelude.glsl");
+        let added_lines = AddedLines {
+            version: true,
+            prelude: Some((prelude, 3)),
+        };
+        let translate = |error: &str| translate_error(error, "foo.glsl", &[7, 8], &added_lines);
+
+        assert_eq!(
+            translate("foo.glsl:1: error: version not supported"),
+            "<#version added by ve_shader>:1: error: version not supported"
+        );
+        assert_eq!(
+            translate("foo.glsl:3:5: error: 'vec5' : no such type"),
+            "shaders/prelude.glsl:2:5: error: 'vec5' : no such type"
+        );
+        assert_eq!(
+            translate("foo.glsl:4: error: in the last prelude line"),
+            "shaders/prelude.glsl:3: error: in the last prelude line"
+        );
+        assert_eq!(
+            translate("foo.glsl:6: error: in the shader"),
+            "foo.glsl:8: error: in the shader"
+        );
+
+        let without_version = AddedLines {
+            version: false,
+            prelude: Some((prelude, 3)),
+        };
+        assert_eq!(
+            translate_error("foo.glsl:1: error: a", "foo.glsl", &[7], &without_version),
+            "shaders/prelude.glsl:1: error: a"
+        );
+        assert_eq!(
+            translate_error("foo.glsl:4: error: b", "foo.glsl", &[7], &without_version),
+            "foo.glsl:7: error: b"
+        );
+    }
+
+    #[test]
+    fn release_overrides_the_profile() {
+        let config = Config::parse("[profile.ci]\ndebug = true\nmax-warnings = 0\n").unwrap();
+        let profile = load_profile("ci", &config).unwrap();
+        assert_eq!(
+            release_settings(false, None, None, &profile),
+            (true, Some(0))
+        );
+        assert_eq!(release_settings(true, None, None, &profile), (false, None));
+        assert_eq!(
+            release_settings(true, Some(true), Some(5), &profile),
+            (true, Some(5))
+        );
+        assert_eq!(
+            release_settings(false, Some(false), Some(5), &profile),
+            (false, Some(5))
+        );
+    }
+
+    #[test]
+    fn validate_checks_the_stripped_interface() {
+        use shaderc::OptimizationLevel::{Performance, Zero};
+        assert!(checks_stripped_interface(false, true, Performance));
+        assert!(checks_stripped_interface(true, false, Performance));
+        assert!(!checks_stripped_interface(false, false, Performance));
+        assert!(!checks_stripped_interface(true, true, Zero));
+    }
+
+    #[test]
+    fn json_emits_the_reflection() {
+        assert_eq!("json".parse::<Emit>().unwrap(), Emit::Reflect);
+        assert_eq!("reflect".parse::<Emit>().unwrap(), Emit::Reflect);
+        assert!("yaml".parse::<Emit>().is_err());
+    }
 }
@@ -0,0 +1,900 @@
+//! Reads the interface of a compiled SPIR-V module
+//!
+//! Only the instructions needed for reflection are decoded; everything else is skipped.
+//! See the [SPIR-V specification](https://www.khronos.org/registry/spir-v/specs/unified1/SPIRV.html)
+//! for the binary layout.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    str::FromStr,
+};
+
+const MAGIC: u32 = 0x0723_0203;
+const HEADER_LEN: usize = 5;
+
+// opcodes
+const OP_NAME: u32 = 5;
+const OP_EXT_INST: u32 = 12;
+const OP_ENTRY_POINT: u32 = 15;
+const OP_EXECUTION_MODE: u32 = 16;
+const OP_CAPABILITY: u32 = 17;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_FUNCTION: u32 = 54;
+const OP_FUNCTION_PARAMETER: u32 = 55;
+const OP_FUNCTION_END: u32 = 56;
+const OP_VARIABLE: u32 = 59;
+const OP_LOAD: u32 = 61;
+const OP_PTR_ACCESS_CHAIN: u32 = 67;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+const OP_IMAGE_SAMPLE_IMPLICIT_LOD: u32 = 87;
+const OP_IMAGE_WRITE: u32 = 99;
+const OP_CONVERT_F_TO_U: u32 = 109;
+const OP_BIT_COUNT: u32 = 205;
+const OP_EMIT_VERTEX: u32 = 218;
+const OP_EMIT_STREAM_VERTEX: u32 = 220;
+const OP_ATOMIC_LOAD: u32 = 227;
+const OP_ATOMIC_XOR: u32 = 242;
+const OP_LOOP_MERGE: u32 = 246;
+const OP_LABEL: u32 = 248;
+const OP_TYPE_ACCELERATION_STRUCTURE: u32 = 5341;
+
+// execution modes
+const MODE_OUTPUT_VERTICES: u32 = 26;
+const MODE_OUTPUT_POINTS: u32 = 27;
+const MODE_OUTPUT_LINE_STRIP: u32 = 28;
+const MODE_OUTPUT_TRIANGLE_STRIP: u32 = 29;
+
+// decorations
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_BUILT_IN: u32 = 11;
+const DECORATION_LOCATION: u32 = 30;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+
+// storage classes
+const STORAGE_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_INPUT: u32 = 1;
+const STORAGE_UNIFORM: u32 = 2;
+const STORAGE_OUTPUT: u32 = 3;
+const STORAGE_PUSH_CONSTANT: u32 = 9;
+const STORAGE_STORAGE_BUFFER: u32 = 12;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SpirvError {
+    #[error("Not a SPIR-V module")]
+    InvalidMagic,
+    #[error("Truncated SPIR-V module")]
+    Truncated,
+}
+
+/// Interface of a SPIR-V module
+#[derive(Debug, Default)]
+pub struct Module {
+    /// major, minor
+    pub version: (u32, u32),
+    pub entry_points: Vec<EntryPoint>,
+    pub capabilities: Vec<Capability>,
+    pub inputs: Vec<Variable>,
+    pub outputs: Vec<Variable>,
+    pub bindings: Vec<Binding>,
+    pub push_constants: Vec<String>,
+    pub instruction_count: InstructionCount,
+    pub geometry: Geometry,
+}
+
+/// What a geometry shader declares and emits
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Geometry {
+    /// `max_vertices` of the output layout
+    pub max_vertices: Option<u32>,
+    /// Vertices of the smallest primitive of the output layout: 1, 2 or 3
+    pub vertices_per_primitive: Option<u32>,
+    /// EmitVertex and EmitStreamVertex instructions
+    pub emit_vertex: usize,
+    /// Whether the functions contain loops, so emits may run more than once
+    pub loops: bool,
+}
+
+/// Instructions in the function bodies, excluding labels and function declarations
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct InstructionCount {
+    pub total: usize,
+    /// Conversions, arithmetic, bit, logical and extended (e.g. GLSL.std.450) instructions
+    pub arithmetic: usize,
+    /// Loads, stores, access chains, image and atomic instructions
+    pub memory: usize,
+}
+
+impl InstructionCount {
+    pub fn add(&mut self, other: InstructionCount) {
+        self.total += other.total;
+        self.arithmetic += other.arithmetic;
+        self.memory += other.memory;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryPoint {
+    pub name: String,
+    pub execution_model: ExecutionModel,
+}
+
+/// Stage input or output
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variable {
+    pub name: String,
+    pub location: Option<u32>,
+    pub built_in: bool,
+}
+
+/// Descriptor set binding
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    pub set: u32,
+    pub binding: u32,
+    pub name: String,
+    pub kind: DescriptorKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExecutionModel {
+    Vertex,
+    TessControl,
+    TessEvaluation,
+    Geometry,
+    Fragment,
+    Compute,
+    Other(u32),
+}
+
+impl From<u32> for ExecutionModel {
+    fn from(value: u32) -> Self {
+        match value {
+            0 => ExecutionModel::Vertex,
+            1 => ExecutionModel::TessControl,
+            2 => ExecutionModel::TessEvaluation,
+            3 => ExecutionModel::Geometry,
+            4 => ExecutionModel::Fragment,
+            5 => ExecutionModel::Compute,
+            other => ExecutionModel::Other(other),
+        }
+    }
+}
+
+impl fmt::Display for ExecutionModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExecutionModel::Vertex => write!(f, "vertex"),
+            ExecutionModel::TessControl => write!(f, "tess control"),
+            ExecutionModel::TessEvaluation => write!(f, "tess evaluation"),
+            ExecutionModel::Geometry => write!(f, "geometry"),
+            ExecutionModel::Fragment => write!(f, "fragment"),
+            ExecutionModel::Compute => write!(f, "compute"),
+            ExecutionModel::Other(value) => write!(f, "execution model {}", value),
+        }
+    }
+}
+
+/// Capability declared by the module
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Capability(pub u32);
+
+/// Names of the capabilities up to SPIR-V 1.0 core
+const CAPABILITY_NAMES: &[&str] = &[
+    "Matrix",
+    "Shader",
+    "Geometry",
+    "Tessellation",
+    "Addresses",
+    "Linkage",
+    "Kernel",
+    "Vector16",
+    "Float16Buffer",
+    "Float16",
+    "Float64",
+    "Int64",
+    "Int64Atomics",
+    "ImageBasic",
+    "ImageReadWrite",
+    "ImageMipmap",
+    "",
+    "Pipes",
+    "Groups",
+    "DeviceEnqueue",
+    "LiteralSampler",
+    "AtomicStorage",
+    "Int16",
+    "TessellationPointSize",
+    "GeometryPointSize",
+    "ImageGatherExtended",
+    "",
+    "StorageImageMultisample",
+    "UniformBufferArrayDynamicIndexing",
+    "SampledImageArrayDynamicIndexing",
+    "StorageBufferArrayDynamicIndexing",
+    "StorageImageArrayDynamicIndexing",
+    "ClipDistance",
+    "CullDistance",
+    "ImageCubeArray",
+    "SampleRateShading",
+    "ImageRect",
+    "SampledRect",
+    "GenericPointer",
+    "Int8",
+    "InputAttachment",
+    "SparseResidency",
+    "MinLod",
+    "Sampled1D",
+    "Image1D",
+    "SampledCubeArray",
+    "SampledBuffer",
+    "ImageBuffer",
+    "ImageMSArray",
+    "StorageImageExtendedFormats",
+    "ImageQuery",
+    "DerivativeControl",
+    "InterpolationFunction",
+    "TransformFeedback",
+    "GeometryStreams",
+    "StorageImageReadWithoutFormat",
+    "StorageImageWriteWithoutFormat",
+    "MultiViewport",
+];
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match CAPABILITY_NAMES.get(self.0 as usize) {
+            Some(name) if !name.is_empty() => f.write_str(name),
+            _ => write!(f, "Capability{}", self.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DescriptorKind {
+    UniformBuffer,
+    StorageBuffer,
+    Sampler,
+    SampledImage,
+    StorageImage,
+    CombinedImageSampler,
+    AccelerationStructure,
+    Unknown,
+}
+
+impl fmt::Display for DescriptorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            DescriptorKind::UniformBuffer => "uniform_buffer",
+            DescriptorKind::StorageBuffer => "storage_buffer",
+            DescriptorKind::Sampler => "sampler",
+            DescriptorKind::SampledImage => "sampled_image",
+            DescriptorKind::StorageImage => "storage_image",
+            DescriptorKind::CombinedImageSampler => "combined_image_sampler",
+            DescriptorKind::AccelerationStructure => "acceleration_structure",
+            DescriptorKind::Unknown => "unknown",
+        })
+    }
+}
+
+impl FromStr for DescriptorKind {
+    type Err = String;
+
+    /// Parses the names printed by `Display`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "uniform_buffer" => Ok(DescriptorKind::UniformBuffer),
+            "storage_buffer" => Ok(DescriptorKind::StorageBuffer),
+            "sampler" => Ok(DescriptorKind::Sampler),
+            "sampled_image" => Ok(DescriptorKind::SampledImage),
+            "storage_image" => Ok(DescriptorKind::StorageImage),
+            "combined_image_sampler" => Ok(DescriptorKind::CombinedImageSampler),
+            "acceleration_structure" => Ok(DescriptorKind::AccelerationStructure),
+            _ => Err(format!("Unknown descriptor type: {}", s)),
+        }
+    }
+}
+
+/// Raw instruction: opcode and operands
+struct Instruction<'a> {
+    opcode: u32,
+    operands: &'a [u32],
+}
+
+/// Decodes a nul-terminated literal string packed into words
+fn literal_string(words: &[u32]) -> (String, usize) {
+    let mut bytes = Vec::new();
+    for (idx, word) in words.iter().enumerate() {
+        for byte in word.to_le_bytes().iter() {
+            if *byte == 0 {
+                return (String::from_utf8_lossy(&bytes).into_owned(), idx + 1);
+            }
+            bytes.push(*byte);
+        }
+    }
+    (String::from_utf8_lossy(&bytes).into_owned(), words.len())
+}
+
+fn instructions(words: &[u32]) -> Result<Vec<Instruction<'_>>, SpirvError> {
+    let mut instructions = Vec::new();
+    let mut pos = HEADER_LEN;
+    while pos < words.len() {
+        let word_count = (words[pos] >> 16) as usize;
+        if word_count == 0 || pos + word_count > words.len() {
+            return Err(SpirvError::Truncated);
+        }
+        instructions.push(Instruction {
+            opcode: words[pos] & 0xffff,
+            operands: &words[pos + 1..pos + word_count],
+        });
+        pos += word_count;
+    }
+    Ok(instructions)
+}
+
+impl Module {
+    pub fn parse(words: &[u32]) -> Result<Module, SpirvError> {
+        if words.len() < HEADER_LEN {
+            return Err(SpirvError::Truncated);
+        }
+        if words[0] != MAGIC {
+            return Err(SpirvError::InvalidMagic);
+        }
+
+        let mut module = Module {
+            version: ((words[1] >> 16) & 0xff, (words[1] >> 8) & 0xff),
+            ..Module::default()
+        };
+
+        let mut names: HashMap<u32, String> = HashMap::new();
+        let mut decorations: HashMap<(u32, u32), u32> = HashMap::new(); // (id, decoration) -> value
+        let mut pointers: HashMap<u32, u32> = HashMap::new(); // pointer type -> pointee type
+        let mut types: HashMap<u32, (u32, &[u32])> = HashMap::new(); // type -> opcode, operands
+        let mut variables: Vec<(u32, u32, u32)> = Vec::new(); // type, id, storage class
+        let mut built_in_blocks: HashSet<u32> = HashSet::new(); // structs with built-in members
+        let mut in_function = false;
+
+        for instruction in instructions(words)? {
+            let operands = instruction.operands;
+
+            // function bodies
+            match instruction.opcode {
+                OP_FUNCTION => in_function = true,
+                OP_FUNCTION_END => in_function = false,
+                OP_FUNCTION_PARAMETER | OP_LABEL => {}
+                opcode if in_function => {
+                    let count = &mut module.instruction_count;
+                    count.total += 1;
+                    match opcode {
+                        OP_EXT_INST | OP_CONVERT_F_TO_U..=OP_BIT_COUNT => count.arithmetic += 1,
+                        OP_LOAD..=OP_PTR_ACCESS_CHAIN
+                        | OP_IMAGE_SAMPLE_IMPLICIT_LOD..=OP_IMAGE_WRITE
+                        | OP_ATOMIC_LOAD..=OP_ATOMIC_XOR => count.memory += 1,
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+
+            match instruction.opcode {
+                OP_NAME if !operands.is_empty() => {
+                    names.insert(operands[0], literal_string(&operands[1..]).0);
+                }
+                OP_ENTRY_POINT if operands.len() >= 2 => {
+                    module.entry_points.push(EntryPoint {
+                        name: literal_string(&operands[2..]).0,
+                        execution_model: ExecutionModel::from(operands[0]),
+                    });
+                }
+                OP_EXECUTION_MODE if operands.len() >= 2 => {
+                    let geometry = &mut module.geometry;
+                    match operands[1] {
+                        MODE_OUTPUT_VERTICES => geometry.max_vertices = operands.get(2).copied(),
+                        MODE_OUTPUT_POINTS => geometry.vertices_per_primitive = Some(1),
+                        MODE_OUTPUT_LINE_STRIP => geometry.vertices_per_primitive = Some(2),
+                        MODE_OUTPUT_TRIANGLE_STRIP => geometry.vertices_per_primitive = Some(3),
+                        _ => {}
+                    }
+                }
+                OP_EMIT_VERTEX | OP_EMIT_STREAM_VERTEX => module.geometry.emit_vertex += 1,
+                OP_LOOP_MERGE => module.geometry.loops = true,
+                OP_CAPABILITY if !operands.is_empty() => {
+                    module.capabilities.push(Capability(operands[0]));
+                }
+                OP_TYPE_POINTER if operands.len() >= 3 => {
+                    pointers.insert(operands[0], operands[2]);
+                }
+                OP_TYPE_IMAGE
+                | OP_TYPE_SAMPLER
+                | OP_TYPE_SAMPLED_IMAGE
+                | OP_TYPE_ARRAY
+                | OP_TYPE_RUNTIME_ARRAY
+                | OP_TYPE_ACCELERATION_STRUCTURE
+                    if !operands.is_empty() =>
+                {
+                    types.insert(operands[0], (instruction.opcode, &operands[1..]));
+                }
+                OP_VARIABLE if operands.len() >= 3 => {
+                    variables.push((operands[0], operands[1], operands[2]));
+                }
+                OP_DECORATE if operands.len() >= 2 => {
+                    let value = operands.get(2).copied().unwrap_or_default();
+                    decorations.insert((operands[0], operands[1]), value);
+                }
+                OP_MEMBER_DECORATE if operands.len() >= 3 && operands[2] == DECORATION_BUILT_IN => {
+                    built_in_blocks.insert(operands[0]);
+                }
+                _ => {}
+            }
+        }
+
+        let name_of = |id: u32| names.get(&id).cloned().unwrap_or_default();
+        for (ty, id, storage_class) in variables {
+            let pointee = pointers.get(&ty).copied().unwrap_or_default();
+            match storage_class {
+                STORAGE_INPUT | STORAGE_OUTPUT => {
+                    let variable = Variable {
+                        name: name_of(id),
+                        location: decorations.get(&(id, DECORATION_LOCATION)).copied(),
+                        // built-in blocks like gl_PerVertex decorate their members
+                        built_in: decorations.contains_key(&(id, DECORATION_BUILT_IN))
+                            || built_in_blocks.contains(&element_type(pointee, &types)),
+                    };
+                    if storage_class == STORAGE_INPUT {
+                        module.inputs.push(variable);
+                    } else {
+                        module.outputs.push(variable);
+                    }
+                }
+                STORAGE_UNIFORM_CONSTANT | STORAGE_UNIFORM | STORAGE_STORAGE_BUFFER => {
+                    let kind = match storage_class {
+                        STORAGE_UNIFORM
+                            if decorations.contains_key(&(
+                                element_type(pointee, &types),
+                                DECORATION_BUFFER_BLOCK,
+                            )) =>
+                        {
+                            DescriptorKind::StorageBuffer
+                        }
+                        STORAGE_UNIFORM => DescriptorKind::UniformBuffer,
+                        STORAGE_STORAGE_BUFFER => DescriptorKind::StorageBuffer,
+                        _ => descriptor_kind(element_type(pointee, &types), &types),
+                    };
+                    module.bindings.push(Binding {
+                        set: decorations
+                            .get(&(id, DECORATION_DESCRIPTOR_SET))
+                            .copied()
+                            .unwrap_or_default(),
+                        binding: decorations
+                            .get(&(id, DECORATION_BINDING))
+                            .copied()
+                            .unwrap_or_default(),
+                        name: name_of(id),
+                        kind,
+                    });
+                }
+                STORAGE_PUSH_CONSTANT => module.push_constants.push(name_of(id)),
+                _ => {}
+            }
+        }
+        module
+            .bindings
+            .sort_by_key(|binding| (binding.set, binding.binding));
+
+        Ok(module)
+    }
+}
+
+/// Strips arrays from a type
+fn element_type(mut ty: u32, types: &HashMap<u32, (u32, &[u32])>) -> u32 {
+    while let Some((OP_TYPE_ARRAY, operands)) | Some((OP_TYPE_RUNTIME_ARRAY, operands)) =
+        types.get(&ty)
+    {
+        match operands.first() {
+            Some(&element) => ty = element,
+            None => break,
+        }
+    }
+    ty
+}
+
+fn descriptor_kind(ty: u32, types: &HashMap<u32, (u32, &[u32])>) -> DescriptorKind {
+    match types.get(&ty) {
+        Some((OP_TYPE_SAMPLER, _)) => DescriptorKind::Sampler,
+        Some((OP_TYPE_SAMPLED_IMAGE, _)) => DescriptorKind::CombinedImageSampler,
+        // operands: sampled type, dim, depth, arrayed, ms, sampled
+        Some((OP_TYPE_IMAGE, operands)) => match operands.get(5) {
+            Some(2) => DescriptorKind::StorageImage,
+            _ => DescriptorKind::SampledImage,
+        },
+        Some((OP_TYPE_ACCELERATION_STRUCTURE, _)) => DescriptorKind::AccelerationStructure,
+        _ => DescriptorKind::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OP_TYPE_VOID: u32 = 19;
+    const OP_TYPE_FLOAT: u32 = 22;
+    const OP_TYPE_VECTOR: u32 = 23;
+    const OP_TYPE_STRUCT: u32 = 30;
+    const OP_TYPE_FUNCTION: u32 = 33;
+    const OP_TYPE_INT: u32 = 21;
+    const OP_CONSTANT: u32 = 43;
+    const OP_STORE: u32 = 62;
+    const OP_F_ADD: u32 = 129;
+    const OP_ATOMIC_I_ADD: u32 = 234;
+    const OP_SELECTION_MERGE: u32 = 247;
+    const OP_RETURN: u32 = 253;
+
+    /// Packs a literal string into nul-terminated words
+    fn string(s: &str) -> Vec<u32> {
+        let mut bytes = s.as_bytes().to_vec();
+        bytes.resize(s.len() / 4 * 4 + 4, 0);
+        bytes
+            .chunks(4)
+            .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect()
+    }
+
+    /// Assembles a SPIR-V 1.0 module from opcodes and operands
+    fn assemble(instructions: &[(u32, Vec<u32>)]) -> Vec<u32> {
+        let mut words = vec![MAGIC, 0x0001_0000, 0, 100, 0];
+        for (opcode, operands) in instructions {
+            words.push(((operands.len() as u32 + 1) << 16) | opcode);
+            words.extend_from_slice(operands);
+        }
+        words
+    }
+
+    fn parse(instructions: &[(u32, Vec<u32>)]) -> Module {
+        Module::parse(&assemble(instructions)).unwrap()
+    }
+
+    /// An empty `main` function with result id 1
+    fn main_function() -> Vec<(u32, Vec<u32>)> {
+        vec![
+            (OP_TYPE_VOID, vec![2]),
+            (OP_TYPE_FUNCTION, vec![3, 2]),
+            (OP_FUNCTION, vec![2, 1, 0, 3]),
+            (OP_LABEL, vec![4]),
+            (OP_RETURN, vec![]),
+            (OP_FUNCTION_END, vec![]),
+        ]
+    }
+
+    #[test]
+    fn rejects_invalid_binaries() {
+        assert!(matches!(
+            Module::parse(&[MAGIC, 0x0001_0000]),
+            Err(SpirvError::Truncated)
+        ));
+        assert!(matches!(
+            Module::parse(&[0x0203_0723, 0x0001_0000, 0, 1, 0]),
+            Err(SpirvError::InvalidMagic)
+        ));
+
+        // the word count runs past the end of the module
+        let mut words = assemble(&[(OP_CAPABILITY, vec![1])]);
+        words.pop();
+        assert!(matches!(Module::parse(&words), Err(SpirvError::Truncated)));
+
+        let mut words = assemble(&[]);
+        words.push(OP_CAPABILITY);
+        assert!(matches!(Module::parse(&words), Err(SpirvError::Truncated)));
+    }
+
+    #[test]
+    fn header_entry_points_and_capabilities() {
+        let mut instructions = vec![
+            (OP_CAPABILITY, vec![1]),
+            (OP_CAPABILITY, vec![2]),
+            (OP_CAPABILITY, vec![4400]),
+            (OP_ENTRY_POINT, [vec![4, 1], string("main")].concat()),
+            (
+                OP_ENTRY_POINT,
+                [vec![5, 1], string("compute_main")].concat(),
+            ),
+        ];
+        instructions.extend(main_function());
+
+        let mut words = assemble(&instructions);
+        words[1] = 0x0001_0300;
+        let module = Module::parse(&words).unwrap();
+
+        assert_eq!(module.version, (1, 3));
+        assert_eq!(
+            module.entry_points,
+            vec![
+                EntryPoint {
+                    name: String::from("main"),
+                    execution_model: ExecutionModel::Fragment,
+                },
+                EntryPoint {
+                    name: String::from("compute_main"),
+                    execution_model: ExecutionModel::Compute,
+                },
+            ]
+        );
+        let capabilities: Vec<String> = module
+            .capabilities
+            .iter()
+            .map(|capability| capability.to_string())
+            .collect();
+        assert_eq!(capabilities, ["Shader", "Geometry", "Capability4400"]);
+    }
+
+    #[test]
+    fn literal_strings() {
+        assert_eq!(literal_string(&string("")), (String::new(), 1));
+        assert_eq!(literal_string(&string("abc")), (String::from("abc"), 1));
+        // a multiple of four needs a whole word for the terminator
+        assert_eq!(literal_string(&string("main")), (String::from("main"), 2));
+        // unterminated strings end with the operands
+        assert_eq!(
+            literal_string(&[u32::from_le_bytes(*b"abcd")]),
+            (String::from("abcd"), 1)
+        );
+    }
+
+    #[test]
+    fn stage_inputs_and_outputs() {
+        let module = parse(&[
+            (OP_NAME, [vec![10], string("in_color")].concat()),
+            (OP_NAME, [vec![11], string("out_color")].concat()),
+            (OP_NAME, [vec![12], string("gl_FragCoord")].concat()),
+            (OP_NAME, [vec![13], string("")].concat()),
+            (OP_DECORATE, vec![10, DECORATION_LOCATION, 2]),
+            (OP_DECORATE, vec![11, DECORATION_LOCATION, 0]),
+            (OP_DECORATE, vec![12, DECORATION_BUILT_IN, 15]),
+            (OP_MEMBER_DECORATE, vec![24, 0, DECORATION_BUILT_IN, 0]),
+            (OP_TYPE_FLOAT, vec![20, 32]),
+            (OP_TYPE_VECTOR, vec![21, 20, 4]),
+            (OP_TYPE_POINTER, vec![22, STORAGE_INPUT, 21]),
+            (OP_TYPE_POINTER, vec![23, STORAGE_OUTPUT, 21]),
+            (OP_TYPE_STRUCT, vec![24, 21]),
+            (OP_TYPE_POINTER, vec![25, STORAGE_OUTPUT, 24]),
+            (OP_VARIABLE, vec![22, 10, STORAGE_INPUT]),
+            (OP_VARIABLE, vec![23, 11, STORAGE_OUTPUT]),
+            (OP_VARIABLE, vec![22, 12, STORAGE_INPUT]),
+            (OP_VARIABLE, vec![25, 13, STORAGE_OUTPUT]),
+        ]);
+
+        let variable = |name: &str, location, built_in| Variable {
+            name: String::from(name),
+            location,
+            built_in,
+        };
+        assert_eq!(
+            module.inputs,
+            vec![
+                variable("in_color", Some(2), false),
+                variable("gl_FragCoord", None, true),
+            ]
+        );
+        // gl_PerVertex only decorates its members
+        assert_eq!(
+            module.outputs,
+            vec![
+                variable("out_color", Some(0), false),
+                variable("", None, true),
+            ]
+        );
+        assert!(module.bindings.is_empty());
+    }
+
+    #[test]
+    fn instruction_counts() {
+        let module = parse(&[
+            (OP_TYPE_VOID, vec![2]),
+            (OP_TYPE_FUNCTION, vec![3, 2]),
+            (OP_TYPE_FLOAT, vec![5, 32]),
+            (OP_TYPE_POINTER, vec![6, 7, 5]),
+            (OP_FUNCTION, vec![2, 1, 0, 3]),
+            (OP_FUNCTION_PARAMETER, vec![6, 8]),
+            (OP_LABEL, vec![4]),
+            (OP_LOAD, vec![5, 9, 8]),
+            (OP_F_ADD, vec![5, 10, 9, 9]),
+            (OP_EXT_INST, vec![5, 11, 1, 4, 10]),
+            (OP_STORE, vec![8, 11]),
+            (OP_ATOMIC_I_ADD, vec![5, 12, 8, 1, 0, 10]),
+            (OP_SELECTION_MERGE, vec![13, 0]),
+            (OP_LABEL, vec![13]),
+            (OP_RETURN, vec![]),
+            (OP_FUNCTION_END, vec![]),
+        ]);
+
+        // types and labels don't count, the selection merge and return do
+        assert_eq!(
+            module.instruction_count,
+            InstructionCount {
+                total: 7,
+                arithmetic: 2,
+                memory: 3,
+            }
+        );
+
+        let mut total = InstructionCount::default();
+        total.add(module.instruction_count);
+        total.add(module.instruction_count);
+        assert_eq!(
+            total,
+            InstructionCount {
+                total: 14,
+                arithmetic: 4,
+                memory: 6,
+            }
+        );
+    }
+
+    #[test]
+    fn descriptor_kinds() {
+        const IMAGE_SAMPLED: u32 = 1;
+        const IMAGE_STORAGE: u32 = 2;
+        let names: Vec<(u32, Vec<u32>)> = [
+            "ubo", "ssbo", "buffer", "samp", "tex", "img", "combined", "tlas", "value", "textures",
+        ]
+        .iter()
+        .enumerate()
+        .map(|(idx, name)| (OP_NAME, [vec![100 + idx as u32], string(name)].concat()))
+        .collect();
+
+        let mut instructions = names;
+        instructions.extend(vec![
+            (OP_DECORATE, vec![31, DECORATION_BUFFER_BLOCK]),
+            (OP_DECORATE, vec![100, DECORATION_DESCRIPTOR_SET, 1]),
+            (OP_DECORATE, vec![100, DECORATION_BINDING, 0]),
+            (OP_DECORATE, vec![101, DECORATION_BINDING, 1]),
+            (OP_DECORATE, vec![102, DECORATION_BINDING, 2]),
+            (OP_DECORATE, vec![103, DECORATION_BINDING, 3]),
+            (OP_DECORATE, vec![104, DECORATION_BINDING, 4]),
+            (OP_DECORATE, vec![105, DECORATION_BINDING, 5]),
+            (OP_DECORATE, vec![106, DECORATION_BINDING, 6]),
+            (OP_DECORATE, vec![107, DECORATION_BINDING, 7]),
+            (OP_DECORATE, vec![108, DECORATION_BINDING, 8]),
+            (OP_DECORATE, vec![109, DECORATION_BINDING, 9]),
+            (OP_TYPE_FLOAT, vec![20, 32]),
+            (OP_TYPE_INT, vec![21, 32, 0]),
+            (OP_CONSTANT, vec![21, 22, 4]),
+            (OP_TYPE_STRUCT, vec![30, 20]),
+            (OP_TYPE_STRUCT, vec![31, 20]),
+            (OP_TYPE_SAMPLER, vec![32]),
+            (OP_TYPE_IMAGE, vec![33, 20, 1, 0, 0, 0, IMAGE_SAMPLED, 0]),
+            (OP_TYPE_IMAGE, vec![34, 20, 1, 0, 0, 0, IMAGE_STORAGE, 1]),
+            (OP_TYPE_SAMPLED_IMAGE, vec![35, 33]),
+            (OP_TYPE_ACCELERATION_STRUCTURE, vec![36]),
+            (OP_TYPE_ARRAY, vec![37, 35, 22]),
+            (OP_TYPE_POINTER, vec![40, STORAGE_UNIFORM, 30]),
+            (OP_TYPE_POINTER, vec![41, STORAGE_UNIFORM, 31]),
+            (OP_TYPE_POINTER, vec![42, STORAGE_STORAGE_BUFFER, 30]),
+            (OP_TYPE_POINTER, vec![43, STORAGE_UNIFORM_CONSTANT, 32]),
+            (OP_TYPE_POINTER, vec![44, STORAGE_UNIFORM_CONSTANT, 33]),
+            (OP_TYPE_POINTER, vec![45, STORAGE_UNIFORM_CONSTANT, 34]),
+            (OP_TYPE_POINTER, vec![46, STORAGE_UNIFORM_CONSTANT, 35]),
+            (OP_TYPE_POINTER, vec![47, STORAGE_UNIFORM_CONSTANT, 36]),
+            (OP_TYPE_POINTER, vec![48, STORAGE_UNIFORM_CONSTANT, 20]),
+            (OP_TYPE_POINTER, vec![49, STORAGE_UNIFORM_CONSTANT, 37]),
+            (OP_TYPE_POINTER, vec![50, STORAGE_PUSH_CONSTANT, 30]),
+            // declared out of binding order
+            (OP_VARIABLE, vec![49, 109, STORAGE_UNIFORM_CONSTANT]),
+            (OP_VARIABLE, vec![40, 100, STORAGE_UNIFORM]),
+            (OP_VARIABLE, vec![41, 101, STORAGE_UNIFORM]),
+            (OP_VARIABLE, vec![42, 102, STORAGE_STORAGE_BUFFER]),
+            (OP_VARIABLE, vec![43, 103, STORAGE_UNIFORM_CONSTANT]),
+            (OP_VARIABLE, vec![44, 104, STORAGE_UNIFORM_CONSTANT]),
+            (OP_VARIABLE, vec![45, 105, STORAGE_UNIFORM_CONSTANT]),
+            (OP_VARIABLE, vec![46, 106, STORAGE_UNIFORM_CONSTANT]),
+            (OP_VARIABLE, vec![47, 107, STORAGE_UNIFORM_CONSTANT]),
+            (OP_VARIABLE, vec![48, 108, STORAGE_UNIFORM_CONSTANT]),
+            (OP_VARIABLE, vec![50, 110, STORAGE_PUSH_CONSTANT]),
+            (OP_NAME, [vec![110], string("constants")].concat()),
+        ]);
+        let module = parse(&instructions);
+
+        let bindings: Vec<(u32, u32, &str, DescriptorKind)> = module
+            .bindings
+            .iter()
+            .map(|binding| {
+                (
+                    binding.set,
+                    binding.binding,
+                    binding.name.as_str(),
+                    binding.kind,
+                )
+            })
+            .collect();
+        assert_eq!(
+            bindings,
+            vec![
+                (0, 1, "ssbo", DescriptorKind::StorageBuffer),
+                (0, 2, "buffer", DescriptorKind::StorageBuffer),
+                (0, 3, "samp", DescriptorKind::Sampler),
+                (0, 4, "tex", DescriptorKind::SampledImage),
+                (0, 5, "img", DescriptorKind::StorageImage),
+                (0, 6, "combined", DescriptorKind::CombinedImageSampler),
+                (0, 7, "tlas", DescriptorKind::AccelerationStructure),
+                (0, 8, "value", DescriptorKind::Unknown),
+                (0, 9, "textures", DescriptorKind::CombinedImageSampler),
+                (1, 0, "ubo", DescriptorKind::UniformBuffer),
+            ]
+        );
+        assert_eq!(module.push_constants, ["constants"]);
+    }
+
+    #[test]
+    fn descriptor_kind_names_round_trip() {
+        for kind in &[
+            DescriptorKind::UniformBuffer,
+            DescriptorKind::StorageBuffer,
+            DescriptorKind::Sampler,
+            DescriptorKind::SampledImage,
+            DescriptorKind::StorageImage,
+            DescriptorKind::CombinedImageSampler,
+            DescriptorKind::AccelerationStructure,
+        ] {
+            assert_eq!(kind.to_string().parse::<DescriptorKind>(), Ok(*kind));
+        }
+        assert!("unknown".parse::<DescriptorKind>().is_err());
+    }
+
+    #[test]
+    fn geometry_execution_modes_and_emits() {
+        let geometry = |mode: u32, body: Vec<(u32, Vec<u32>)>| {
+            let mut instructions = vec![
+                (OP_EXECUTION_MODE, vec![1, MODE_OUTPUT_VERTICES, 4]),
+                (OP_EXECUTION_MODE, vec![1, mode]),
+                (OP_TYPE_VOID, vec![2]),
+                (OP_TYPE_FUNCTION, vec![3, 2]),
+                (OP_FUNCTION, vec![2, 1, 0, 3]),
+                (OP_LABEL, vec![4]),
+            ];
+            instructions.extend(body);
+            instructions.push((OP_RETURN, vec![]));
+            instructions.push((OP_FUNCTION_END, vec![]));
+            parse(&instructions).geometry
+        };
+
+        assert_eq!(
+            geometry(
+                MODE_OUTPUT_TRIANGLE_STRIP,
+                vec![
+                    (OP_EMIT_VERTEX, vec![]),
+                    (OP_EMIT_VERTEX, vec![]),
+                    (OP_EMIT_STREAM_VERTEX, vec![5]),
+                ]
+            ),
+            Geometry {
+                max_vertices: Some(4),
+                vertices_per_primitive: Some(3),
+                emit_vertex: 3,
+                loops: false,
+            }
+        );
+        assert_eq!(
+            geometry(
+                MODE_OUTPUT_LINE_STRIP,
+                vec![(OP_LOOP_MERGE, vec![6, 7, 0]), (OP_EMIT_VERTEX, vec![]),]
+            ),
+            Geometry {
+                max_vertices: Some(4),
+                vertices_per_primitive: Some(2),
+                emit_vertex: 1,
+                loops: true,
+            }
+        );
+        assert_eq!(
+            geometry(MODE_OUTPUT_POINTS, vec![]).vertices_per_primitive,
+            Some(1)
+        );
+        assert_eq!(parse(&main_function()).geometry, Geometry::default());
+    }
+}